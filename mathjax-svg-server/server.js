@@ -50,6 +50,33 @@ const argv = yargs(hideBin(process.argv))
 
 // --- Main Logic ---
 
+// An error about the request or its content, sent back over stdio as
+// {message, kind, line?, column?}
+class RenderError extends Error {
+  constructor(message, kind, position = {}) {
+    super(message);
+    this.kind = kind;
+    this.line = position.line;
+    this.column = position.column;
+  }
+
+  toJSON() {
+    return { message: this.message, kind: this.kind, line: this.line, column: this.column };
+  }
+}
+
+// Find the 1-based line and column of the control sequence a MathJax
+// message complains about, e.g. "Undefined control sequence \foo"
+function locateError(source, message) {
+  const match = message.match(/\\[A-Za-z]+|\\./);
+  const index = match ? source.indexOf(match[0]) : -1;
+  if (index < 0) {
+    return {};
+  }
+  const before = source.slice(0, index).split('\n');
+  return { line: before.length, column: before[before.length - 1].length + 1 };
+}
+
 async function convertEquation(equation, format, inline = false) {
   try {
     const data = await mjAPI.typeset({
@@ -62,7 +89,8 @@ async function convertEquation(equation, format, inline = false) {
     if (!data.errors) {
       return data.svg;
     } else {
-      throw new Error(`MathJax error: ${data.errors.join(', ')}`);
+      const message = data.errors.join(', ').replace(/^TeX parse error: /, '');
+      throw new RenderError(message, format.toLowerCase(), locateError(equation, message));
     }
   } catch (error) {
     console.error("Error during MathJax processing:", error);
@@ -71,18 +99,20 @@ async function convertEquation(equation, format, inline = false) {
 }
 
 // Handle JSON-RPC style requests
-// Process a MathJax request in the format {inline: boolean, content: string}
+// Process a MathJax request in the format {format?: string, inline: boolean, content: string}
 async function processMathJaxRequest(request) {
   if (request.content === undefined) {
-    throw new Error('Content is required');
+    throw new RenderError('Content is required', 'request');
   }
 
   if (request.inline === undefined) {
-    throw new Error('Inline flag is required');
+    throw new RenderError('Inline flag is required', 'request');
   }
 
-  // const format = inline ? "Inline" : "Equation";
-  const format = 'TeX';
+  const format = request.format ?? 'TeX';
+  if (!allowedFormats.includes(format)) {
+    throw new RenderError(`Invalid format. Supported formats: ${allowedFormats.join(', ')}`, 'request');
+  }
 
   return await convertEquation(request.content, format, request.inline);
 }
@@ -119,10 +149,16 @@ function generateId() {
   return Math.random().toString(36).substring(2, 15);
 }
 
+// Write one response line to stdout
+// Responses look like {id, ok: svg} or {id, error: {message, kind, line?, column?}}
+function reply(response) {
+  process.stdout.write(JSON.stringify(response) + '\n');
+}
+
 // Run in stdio mode
 async function runStdioMode() {
   console.error('Running in stdio mode');
-  console.error('Send requests in format: {method: string, inline: boolean, content: string}');
+  console.error('Send requests in format: {id: number, method: string, format?: string, inline: boolean, content: string}');
   
   const rl = createInterface({
     input: process.stdin,
@@ -130,23 +166,27 @@ async function runStdioMode() {
     terminal: false
   });
   
+  // Requests are handled concurrently, the id ties each response to its request
   rl.on('line', async (line) => {
+    let id = null;
     try {
       const request = JSON.parse(line);
+      id = request.id ?? null;
       let method = request.method;
       if (method == "mathjax") {
         const svg = await processMathJaxRequest(request);
-        // Send SVG as response
-        console.log(svg);
-      } else if (method == "mermaid") {
+        reply({ id, ok: svg });
       } else {
-        console.error("wrong method");
+        throw new RenderError(`Unsupported method: ${method}`, 'request');
       }
       
     } catch (e) {
-      // Handle errors
+      // Report errors without taking the process down
       console.error(`Error: ${e.message}`);
-      process.exit(1);
+      const error = e instanceof RenderError
+        ? e
+        : new RenderError(e.message, e instanceof SyntaxError ? 'request' : 'internal');
+      reply({ id, error });
     }
   });
   
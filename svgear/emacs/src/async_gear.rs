@@ -1,40 +1,94 @@
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
-use emacs::{Env, Result, UnibyteString};
+use emacs::{Env, Error, Result, UnibyteString};
 use svgear::{
+    error::SvgearError,
+    manager::Bitmap,
+    single_flight::SingleFlight,
     tokio::{
         self,
         sync::{Mutex, RwLock},
         task::JoinSet,
     },
-    PaintParams, PaintType, RenderRequest, SvgManager, Svgear,
+    PaintParams, PaintType, RenderOptions, RenderRequest, SvgManager, Svgear,
 };
 
-use crate::raw_value::RawValue;
+use crate::raw_value::{CallArg, RawValue};
+
+/// Paints that take longer are abandoned and their Node.js process recycled
+const PAINT_TIMEOUT_MS: u64 = 10_000;
 
 #[derive(Debug, Clone)]
 pub struct CallbackWithArg {
     val: RawValue,
-    arg: UnibyteString,
+    args: Vec<CallArg>,
 }
 
 impl CallbackWithArg {
-    pub fn new(val: RawValue, arg: UnibyteString) -> Self {
-        Self { val, arg }
+    pub fn new(val: RawValue, args: Vec<CallArg>) -> Self {
+        Self { val, args }
+    }
+
+    /// Call back with `(DATA ASCENT ERROR)`
+    ///
+    /// DATA is the PNG and ASCENT the percentage (or nil) for `create-image`.
+    /// On failure both are nil and ERROR is `(KIND MESSAGE)`, KIND being a
+    /// symbol such as `timeout`.
+    pub fn from_result(val: RawValue, result: Result<Bitmap>) -> Self {
+        match result {
+            Ok(bitmap) => Self::new(
+                val,
+                vec![data(bitmap.clone()), ascent(&bitmap), CallArg::Nil],
+            ),
+            Err(e) => Self::new(val, vec![CallArg::Nil, CallArg::Nil, error(&e)]),
+        }
+    }
+
+    /// Call back with an error image as DATA and the error that caused it as ERROR
+    pub fn from_error_image(val: RawValue, bitmap: Bitmap, e: &Error) -> Self {
+        Self::new(val, vec![data(bitmap.clone()), ascent(&bitmap), error(e)])
     }
 
     pub fn resolve(self, env: &Env) -> RawValue {
         let mut cb = self.val;
         cb.replace_env(env);
-        cb.call(self.arg);
+        cb.call(self.args);
         cb
     }
 }
 
+fn data(bitmap: Bitmap) -> CallArg {
+    CallArg::Unibyte(UnibyteString::new(bitmap.data))
+}
+
+fn ascent(bitmap: &Bitmap) -> CallArg {
+    match bitmap.metrics {
+        Some(metrics) => CallArg::Integer(metrics.ascent_percent() as i64),
+        None => CallArg::Nil,
+    }
+}
+
+fn error(e: &Error) -> CallArg {
+    let kind = SvgearError::kind_of(e).unwrap_or("error");
+    CallArg::List(vec![
+        CallArg::Symbol(kind),
+        CallArg::String(format!("{:#}", e)),
+    ])
+}
+
 pub struct AsyncGear {
     pub runtime: tokio::runtime::Runtime,
     pub set: Mutex<JoinSet<Result<CallbackWithArg>>>,
     pub gear: Arc<RwLock<Svgear>>,
+    /// Script the Node.js workers run
+    pub exe_path: String,
+    /// Call back with an error image instead of nil when painting fails
+    pub error_images: AtomicBool,
+    /// Padding and border of every render, see `svgear-set-frame`
+    pub frame: std::sync::Mutex<RenderOptions>,
+    /// Identical renders in progress share one bitmap
+    pub inflight: SingleFlight<Bitmap>,
 }
 
 impl AsyncGear {
@@ -43,9 +97,17 @@ impl AsyncGear {
             .worker_threads(4)
             .enable_all()
             .build()?;
-        let gear = Arc::new(RwLock::new(Svgear::new(exe_path)));
+        let gear = Arc::new(RwLock::new(Svgear::new(exe_path.clone())));
         let set = Mutex::new(JoinSet::new());
-        Ok(Self { runtime, gear, set })
+        Ok(Self {
+            runtime,
+            gear,
+            exe_path,
+            set,
+            error_images: AtomicBool::new(false),
+            frame: std::sync::Mutex::new(RenderOptions::default()),
+            inflight: SingleFlight::new(),
+        })
     }
 
     pub fn render_svg(
@@ -54,26 +116,40 @@ impl AsyncGear {
         content: String,
         width: Option<u32>,
         height: Option<u32>,
+        options: RenderOptions,
     ) -> Result<()> {
         let id = SvgManager::generate_id(&content);
+        let key = SvgManager::bitmap_id(&id, width, height, &options);
         let obj = self.gear.clone();
+        let inflight = self.inflight.clone();
         self.runtime.block_on(async {
             self.set.lock().await.spawn(async move {
-                let mut gear = obj.write_owned().await;
-                let resp = gear.manager.process_render_request(RenderRequest {
-                    id: Some(id),
-                    svg_data: content,
-                    width,
-                    height,
-                })?;
-                let data_str = UnibyteString::new(resp.bitmap.data);
-                let res = CallbackWithArg::new(val, data_str);
-                Ok(res)
+                let bitmap = inflight
+                    .run(&key, || {
+                        render_bitmap(obj, id, content, width, height, options)
+                    })
+                    .await;
+                Ok(CallbackWithArg::from_result(val, bitmap))
             })
         });
         Ok(())
     }
 
+    /// Fill in the margin, padding and border that `options` leave unset from the frame
+    fn framed(&self, options: RenderOptions) -> RenderOptions {
+        let frame = self.frame.lock().unwrap();
+        RenderOptions {
+            margin: options.margin.or_else(|| frame.margin.clone()),
+            padding: options.padding.or_else(|| frame.padding.clone()),
+            border: options.border.or_else(|| frame.border.clone()),
+            border_width: options.border_width.or(frame.border_width),
+            border_radius: options
+                .border_radius
+                .or_else(|| frame.border_radius.clone()),
+            ..options
+        }
+    }
+
     pub fn render_input(
         &self,
         val: RawValue,
@@ -81,23 +157,24 @@ impl AsyncGear {
         content: String,
         width: Option<u32>,
         height: Option<u32>,
+        options: RenderOptions,
     ) -> Result<()> {
+        let options = self.framed(options);
+        let key = format!("{ty}\n{width:?}\n{height:?}\n{options:?}\n{content}");
         let obj = self.gear.clone();
+        let inflight = self.inflight.clone();
+        let error_images = self.error_images.load(Ordering::Relaxed);
         self.runtime.block_on(async {
             self.set.lock().await.spawn(async move {
-                let mut gear = obj.write_owned().await;
-                let svg_data = gear.painter.paint(PaintParams { ty, content }).await?;
-                println!("render_input: {svg_data}");
-                let id = SvgManager::generate_id(&svg_data);
-                let resp = gear.manager.process_render_request(RenderRequest {
-                    svg_data,
-                    width,
-                    height,
-                    id: Some(id),
-                })?;
-                let data_str = UnibyteString::new(resp.bitmap.data);
-                let res = CallbackWithArg::new(val, data_str);
-                Ok(res)
+                let paint = paint_bitmap(obj.clone(), ty, content, width, height, options.clone());
+                let bitmap = inflight.run(&key, || paint).await;
+                Ok(match bitmap {
+                    Err(e) if error_images => match error_bitmap(obj, &e, options).await {
+                        Ok(image) => CallbackWithArg::from_error_image(val, image, &e),
+                        Err(_) => CallbackWithArg::from_result(val, Err(e)),
+                    },
+                    bitmap => CallbackWithArg::from_result(val, bitmap),
+                })
             })
         });
         Ok(())
@@ -122,6 +199,72 @@ impl AsyncGear {
         }
         Ok(())
     }
+}
 
+/// Render an SVG to a bitmap
+async fn render_bitmap(
+    gear: Arc<RwLock<Svgear>>,
+    id: String,
+    content: String,
+    width: Option<u32>,
+    height: Option<u32>,
+    options: RenderOptions,
+) -> Result<Bitmap> {
+    let manager = gear.read().await.manager.clone();
+    let resp = manager
+        .render(RenderRequest {
+            id: Some(id),
+            svg_data: content,
+            width,
+            height,
+            options,
+        })
+        .await?;
+    Ok(resp.bitmap)
 }
 
+/// Paint content to SVG and render it to a bitmap
+async fn paint_bitmap(
+    gear: Arc<RwLock<Svgear>>,
+    ty: PaintType,
+    content: String,
+    width: Option<u32>,
+    height: Option<u32>,
+    options: RenderOptions,
+) -> Result<Bitmap> {
+    // Neither painting nor rendering holds the lock, so requests run in parallel
+    let (painter, manager) = {
+        let gear = gear.read().await;
+        (gear.painter.clone(), gear.manager.clone())
+    };
+    let svg_data = painter
+        .paint(PaintParams {
+            ty,
+            content,
+            timeout_ms: Some(PAINT_TIMEOUT_MS),
+        })
+        .await?;
+    println!("render_input: {svg_data}");
+    let id = SvgManager::generate_id(&svg_data);
+    let resp = manager
+        .render(RenderRequest {
+            svg_data,
+            width,
+            height,
+            id: Some(id),
+            options,
+        })
+        .await?;
+    Ok(resp.bitmap)
+}
+
+/// Render an image showing the error, at its natural size
+async fn error_bitmap(
+    gear: Arc<RwLock<Svgear>>,
+    e: &Error,
+    options: RenderOptions,
+) -> Result<Bitmap> {
+    let svg_data = SvgManager::error_svg(&e.to_string());
+    let id = SvgManager::generate_id(&svg_data);
+    render_bitmap(gear, id, svg_data, None, None, options).await
+}
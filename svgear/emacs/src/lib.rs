@@ -1,7 +1,9 @@
 use emacs::{defun, Env, Result, UnibyteString, Value};
 use raw_value::RawValue;
 use std::{io::Write, sync::OnceLock};
-use svgear::PaintType;
+use svgear::disk_cache::{DiskCache, DEFAULT_MAX_BYTES};
+use svgear::painter::NodeServerPool;
+use svgear::{FontConfig, PaintType, RenderOptions};
 
 mod async_gear;
 mod raw_value;
@@ -10,6 +12,12 @@ use async_gear::AsyncGear;
 static GEAR: OnceLock<AsyncGear> = OnceLock::new();
 emacs::plugin_is_GPL_compatible!();
 
+/// Render TeX `content` to PNG
+///
+/// FOREGROUND is the color of the math, e.g. (face-foreground 'default),
+/// and BACKGROUND fills the image; both are optional. SCALE multiplies the
+/// size in pixels, so that the image stays crisp when shown with
+/// `:scale (/ 1.0 SCALE)` on HiDPI screens.
 #[defun]
 fn render_math_to_png(
     callback: Value,
@@ -17,6 +25,9 @@ fn render_math_to_png(
     inline: u8,
     width: Option<u32>,
     height: Option<u32>,
+    foreground: Option<String>,
+    background: Option<String>,
+    scale: Option<f64>,
 ) -> Result<()> {
     let ty = if inline == 1 {
         PaintType::InlineTeX
@@ -26,11 +37,136 @@ fn render_math_to_png(
     let gear = GEAR.get().unwrap();
     let mut raw = RawValue::from(callback);
     raw.make_global();
-    gear.render_input(raw, ty, content, width, height)?;
+    let options = RenderOptions {
+        foreground,
+        background,
+        scale: scale.map(|s| s as f32),
+        ..RenderOptions::default()
+    };
+    gear.render_input(raw, ty, content, width, height, options)?;
     println!("calling render-math-to-png with callback: {:?}", callback);
     Ok(())
 }
 
+/// Render `content` of input type `ty` (e.g. "mathml" or "inlineasciimath") to PNG
+#[defun]
+fn render_to_png(
+    callback: Value,
+    content: String,
+    ty: String,
+    width: Option<u32>,
+    height: Option<u32>,
+    foreground: Option<String>,
+    background: Option<String>,
+    scale: Option<f64>,
+) -> Result<()> {
+    let ty: PaintType = ty.parse()?;
+    let gear = GEAR.get().unwrap();
+    let mut raw = RawValue::from(callback);
+    raw.make_global();
+    let options = RenderOptions {
+        foreground,
+        background,
+        scale: scale.map(|s| s as f32),
+        ..RenderOptions::default()
+    };
+    gear.render_input(raw, ty, content, width, height, options)?;
+    Ok(())
+}
+
+/// Paint with WORKERS Node.js processes, 1 by default
+///
+/// Each process runs its own MathJax instance, so more of them only pay off
+/// when many formulas are painted at once.
+#[defun]
+fn set_workers(workers: usize) -> Result<()> {
+    let gear = GEAR.get().unwrap();
+    let pool = NodeServerPool::new(gear.exe_path.clone(), workers);
+    gear.runtime
+        .block_on(async { gear.gear.write().await.painter.set_node_pool(pool) });
+    Ok(())
+}
+
+/// When ENABLE is non-nil, content that fails to paint is rendered as a red
+/// error image, passed to the callback together with the error
+#[defun]
+fn set_error_images(enable: Value) -> Result<()> {
+    let gear = GEAR.get().unwrap();
+    gear.error_images
+        .store(enable.is_not_nil(), std::sync::atomic::Ordering::Relaxed);
+    Ok(())
+}
+
+/// Pad every rendered image and optionally draw a border around it
+///
+/// PADDING, RADIUS and MARGIN are lengths such as "4px" or "0.25em". BORDER
+/// is the color of the border, which is WIDTH pixels wide, 1 by default.
+/// The BACKGROUND of a render fills the padding up to the rounded corners,
+/// and the MARGIN outside the border stays transparent.
+#[defun]
+fn set_frame(
+    padding: Option<String>,
+    border: Option<String>,
+    width: Option<f64>,
+    radius: Option<String>,
+    margin: Option<String>,
+) -> Result<()> {
+    let gear = GEAR.get().unwrap();
+    *gear.frame.lock().unwrap() = RenderOptions {
+        margin,
+        padding,
+        border,
+        border_width: width.map(|w| w as f32),
+        border_radius: radius,
+        ..RenderOptions::default()
+    };
+    Ok(())
+}
+
+/// Keep painted SVGs and bitmaps in DIR across sessions, shared with
+/// `svgear serve` and the CLI when they use the same directory
+#[defun]
+fn set_cache_dir(dir: String, max_bytes: Option<u64>) -> Result<()> {
+    let cache = DiskCache::open(dir, max_bytes.unwrap_or(DEFAULT_MAX_BYTES))?;
+    let gear = GEAR.get().unwrap();
+    gear.runtime
+        .block_on(async { gear.gear.write().await.set_disk_cache(Some(cache)) });
+    Ok(())
+}
+
+/// Load fonts for `<text>` elements, e.g. in Mermaid diagrams
+///
+/// SYSTEM non-nil keeps the fonts installed on the system. PATHS is a list
+/// of font files and directories separated by `path-separator`. SERIF,
+/// SANS-SERIF and MONOSPACE name the families used for the generic ones.
+/// Returns the number of font faces loaded.
+#[defun]
+fn set_fonts(
+    system: Value,
+    paths: Option<String>,
+    serif: Option<String>,
+    sans_serif: Option<String>,
+    monospace: Option<String>,
+) -> Result<usize> {
+    let mut config = FontConfig {
+        system_fonts: system.is_not_nil(),
+        serif_family: serif,
+        sans_serif_family: sans_serif,
+        monospace_family: monospace,
+        ..FontConfig::default()
+    };
+    for path in std::env::split_paths(paths.as_deref().unwrap_or_default()) {
+        if path.is_dir() {
+            config.font_dirs.push(path);
+        } else {
+            config.font_files.push(path);
+        }
+    }
+    let gear = GEAR.get().unwrap();
+    gear.runtime
+        .block_on(async { gear.gear.read().await.manager.set_fonts(config) })
+}
+
 const SERVER: &[u8] = include_bytes!("../../../mathjax-svg-server/server");
 
 #[emacs::module(name = "svgear-dyn", defun_prefix = "svgear")]
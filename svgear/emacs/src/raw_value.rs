@@ -1,4 +1,6 @@
 use std::borrow::BorrowMut;
+use std::ffi::CString;
+use std::os::raw::c_char;
 
 use emacs::{raw::emacs_value, Env, IntoLisp, Result, UnibyteString, Value};
 use emacs_module::emacs_env_31;
@@ -29,11 +31,13 @@ impl RawValue {
         self.raw_env = env.raw;
     }
 
-    pub fn call(self, arg: UnibyteString) {
+    pub fn call(self, args: Vec<CallArg>) {
         unsafe {
             let funcall = (*self.raw_env).funcall.unwrap();
-            let arg = make_unibyte_string(arg, self.raw_env);
-            let mut args = vec![arg];
+            let mut args: Vec<emacs_value> = args
+                .into_iter()
+                .map(|arg| arg.into_raw(self.raw_env))
+                .collect();
             let lisp_args: &mut [emacs_value] = args.borrow_mut();
             let ptr = lisp_args.as_mut_ptr();
             let length = lisp_args.len() as isize;
@@ -43,13 +47,61 @@ impl RawValue {
     }
 }
 
+/// An argument for a callback invoked after the originating `Env` is gone
+#[derive(Debug, Clone)]
+pub enum CallArg {
+    Unibyte(UnibyteString),
+    String(String),
+    Integer(i64),
+    Symbol(&'static str),
+    List(Vec<CallArg>),
+    Nil,
+}
+
+impl CallArg {
+    fn into_raw(self, env: *mut emacs_env_31) -> emacs_value {
+        match self {
+            CallArg::Unibyte(str) => make_unibyte_string(str, env),
+            CallArg::String(str) => unsafe {
+                let fun = (*env).make_string.unwrap();
+                fun(env, str.as_ptr() as *const c_char, str.len() as isize)
+            },
+            CallArg::Integer(i) => unsafe {
+                let fun = (*env).make_integer.unwrap();
+                fun(env, i as _)
+            },
+            CallArg::Symbol(name) => intern(name, env),
+            CallArg::List(items) => unsafe {
+                let mut items: Vec<emacs_value> =
+                    items.into_iter().map(|item| item.into_raw(env)).collect();
+                let funcall = (*env).funcall.unwrap();
+                funcall(
+                    env,
+                    intern("list", env),
+                    items.len() as isize,
+                    items.as_mut_ptr(),
+                )
+            },
+            CallArg::Nil => intern("nil", env),
+        }
+    }
+}
+
+fn intern(name: &str, env: *mut emacs_env_31) -> emacs_value {
+    let name = CString::new(name).unwrap();
+    unsafe {
+        let fun = (*env).intern.unwrap();
+        fun(env, name.as_ptr())
+    }
+}
+
 fn make_unibyte_string(str: UnibyteString, env: *mut emacs_env_31) -> emacs_value {
     let bytes = str.content;
     let len = bytes.len();
     let ptr = bytes.as_ptr();
     let res = unsafe {
         let fun = (*env).make_unibyte_string.unwrap();
-        fun(env, ptr as *const std::os::raw::c_char, len as isize)
+        fun(env, ptr as *const c_char, len as isize)
     };
     res
 }
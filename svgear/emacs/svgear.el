@@ -1,16 +1,35 @@
 
-(defun svgear-callback (data)
+(defun svgear-callback (data ascent error)
   ;; (print data)
-  (let ((img (create-image data 'png t)))
-    (put-image img 1)))
+  (when error
+    (message "svgear %s: %s" (car error) (cadr error)))
+  ;; With error images enabled, DATA shows the error
+  (when data
+    (let ((img (create-image data 'png t :ascent (or ascent 'center))))
+      (put-image img 1))))
 
 (add-to-list 'load-path (expand-file-name "./"))
 ;; (rs-module/load (expand-file-name "svgear-dyn.so"))
 (module-load (expand-file-name "svgear-dyn.so"))
 ;; (svgear-callback (svgear-test1))
 
+(svgear-set-error-images t)
+(svgear-set-cache-dir (expand-file-name "svgear" user-emacs-directory))
+(svgear-set-fonts t (expand-file-name "../fonts"))
+(svgear-set-frame "0.25em" nil nil "3px")
 (svgear-render-math-to-png 'svgear-callback "adsf" 1 100 100)
 (svgear-render-math-to-png 'svgear-callback "a" 2 100 100)
+(svgear-render-to-png 'svgear-callback "<math><mi>x</mi></math>" "inlinemathml" 100 100)
+(svgear-render-to-png 'svgear-callback "sum_(i=1)^n i^3" "asciimath" nil nil)
+(svgear-render-to-png 'svgear-callback "sum_(i=1)^n i^3" "asciimath" nil nil
+                      (face-foreground 'default) (face-background 'default))
+;; Twice the pixels, shown at the logical size
+(svgear-render-to-png (lambda (data ascent error)
+                        (when data
+                          (put-image (create-image data 'png t :ascent (or ascent 'center)
+                                                   :scale 0.5)
+                                     1)))
+                      "sum_(i=1)^n i^3" "asciimath" nil nil nil nil 2.0)
 (svgear-resolve-one)
 (svgear-resolve)
 ;; (svgear-test1)
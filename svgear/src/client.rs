@@ -1,5 +1,9 @@
-use crate::manager::{GetBitmapRequest, GetBitmapResponse, RenderRequest, RenderResponse};
-use crate::rpc::{Method, RpcRequest, RpcResponse};
+use crate::fonts::FontConfig;
+use crate::manager::{
+    CacheStats, GetBitmapRequest, GetBitmapResponse, RenderOptions, RenderRequest, RenderResponse,
+};
+use crate::painter::PoolStats;
+use crate::rpc::{FontsResult, Method, RpcRequest, RpcResponse};
 use anyhow::{anyhow, Result};
 use reqwest::Client;
 use serde::de::DeserializeOwned;
@@ -67,12 +71,25 @@ impl SvgClient {
         svg_data: &str,
         width: Option<u32>,
         height: Option<u32>,
+    ) -> Result<RenderResponse> {
+        self.render_svg_with_options(svg_data, width, height, RenderOptions::default())
+            .await
+    }
+
+    /// Render an SVG with the given colors
+    pub async fn render_svg_with_options(
+        &self,
+        svg_data: &str,
+        width: Option<u32>,
+        height: Option<u32>,
+        options: RenderOptions,
     ) -> Result<RenderResponse> {
         let request = RenderRequest {
             svg_data: svg_data.to_string(),
             width,
             height,
             id: None,
+            options,
         };
 
         self.send_request(Method::RenderSvg, request).await
@@ -85,6 +102,21 @@ impl SvgClient {
         self.send_request(Method::GetBitmap, request).await
     }
 
+    /// Get the load on the server's Node worker pool
+    pub async fn get_pool_stats(&self) -> Result<PoolStats> {
+        self.send_request(Method::GetPoolStats, ()).await
+    }
+
+    /// Load fonts for `<text>` elements on the server
+    pub async fn set_fonts(&self, config: FontConfig) -> Result<FontsResult> {
+        self.send_request(Method::SetFonts, config).await
+    }
+
+    /// Get the size and eviction counts of the server's caches
+    pub async fn get_cache_stats(&self) -> Result<CacheStats> {
+        self.send_request(Method::GetCacheStats, ()).await
+    }
+
     /// Save a bitmap to a file
     pub async fn save_bitmap(&self, id: &str, path: &str) -> Result<()> {
         let response = self.get_bitmap(id).await?;
@@ -0,0 +1,216 @@
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// Bumped whenever the cached formats change, so that stale entries are not read back
+pub const CACHE_VERSION: u32 = 2;
+
+/// Default size cap of a [`DiskCache`], 256 MiB
+pub const DEFAULT_MAX_BYTES: u64 = 256 << 20;
+
+/// Subdirectory of the cache directory that svgear owns
+const CACHE_SUBDIR: &str = "svgear-cache";
+
+/// Marker file in each version directory, following the Cache Directory Tagging
+/// Specification so that backup tools skip it
+const MARKER: &str = "CACHEDIR.TAG";
+
+/// Contents of the marker file, the required signature followed by a comment
+///
+/// Version directories are recognized by it, so it must stay the same across versions.
+const MARKER_CONTENTS: &str =
+    "Signature: 8a477f597d28d172789f06886806bc55\n# This directory holds svgear's cache.\n";
+
+/// What a [`DiskCache`] entry holds, each kind living in its own directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheKind {
+    /// SVGs painted by a [`Painter`](crate::Painter), keyed by paint type and content
+    Svg,
+    /// Bitmaps rendered by an [`SvgManager`](crate::SvgManager), keyed by SVG content and options
+    Bitmap,
+}
+
+impl CacheKind {
+    fn dir_name(self) -> &'static str {
+        match self {
+            CacheKind::Svg => "svg",
+            CacheKind::Bitmap => "bitmap",
+        }
+    }
+}
+
+/// A content addressed cache on disk, shared between processes
+///
+/// Entries live in `<dir>/svgear-cache/v<CACHE_VERSION>/<kind>/<xx>/<hash>`,
+/// where `hash` is the SHA-256 of the key and `xx` its first two digits.
+/// Each version directory carries a `CACHEDIR.TAG` marker, and on open
+/// the marked directories of older versions are removed; newer versions are
+/// kept for the svgear builds that use them, and nothing else in `dir` is touched. Writes go to a temporary file that is
+/// renamed into place, so readers never see partial entries. Reads refresh
+/// the modification time, and once the cache grows past its size cap the
+/// least recently used entries are removed.
+#[derive(Debug, Clone)]
+pub struct DiskCache {
+    root: PathBuf,
+    max_bytes: u64,
+    /// Estimated size of all entries, kept in sync with the directory when pruning
+    bytes: Arc<AtomicU64>,
+}
+
+impl DiskCache {
+    /// Open or create the cache in `dir`, keeping it under `max_bytes`
+    pub fn open(dir: impl AsRef<Path>, max_bytes: u64) -> Result<Self> {
+        let dir = dir.as_ref().join(CACHE_SUBDIR);
+        let root = dir.join(format!("v{}", CACHE_VERSION));
+        fs::create_dir_all(&root)
+            .with_context(|| format!("Failed to create cache directory {}", root.display()))?;
+        if !is_marked(&root) {
+            fs::write(root.join(MARKER), MARKER_CONTENTS)
+                .with_context(|| format!("Failed to mark cache directory {}", root.display()))?;
+        }
+
+        // Older versions are not read anymore, newer ones may belong to another svgear build
+        for entry in fs::read_dir(&dir)?.flatten() {
+            let path = entry.path();
+            let older = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.strip_prefix('v'))
+                .and_then(|version| version.parse::<u32>().ok())
+                .is_some_and(|version| version < CACHE_VERSION);
+            if older && path.is_dir() && is_marked(&path) {
+                log::info!("Removing cache of an older version: {}", path.display());
+                let _ = fs::remove_dir_all(&path);
+            }
+        }
+
+        let cache = DiskCache {
+            root,
+            max_bytes,
+            bytes: Arc::new(AtomicU64::new(0)),
+        };
+        let bytes = cache.entries().iter().map(|(_, size, _)| size).sum();
+        cache.bytes.store(bytes, Ordering::Relaxed);
+        Ok(cache)
+    }
+
+    /// Directory holding the entries of this version
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Total size of the entries in bytes, as last seen by this process
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    fn path(&self, kind: CacheKind, key: &str) -> PathBuf {
+        let hash = format!("{:x}", Sha256::digest(key.as_bytes()));
+        self.root.join(kind.dir_name()).join(&hash[..2]).join(hash)
+    }
+
+    /// Read an entry, marking it as recently used
+    pub fn get(&self, kind: CacheKind, key: &str) -> Option<Vec<u8>> {
+        let path = self.path(kind, key);
+        let data = fs::read(&path).ok()?;
+        if let Ok(file) = fs::File::options().write(true).open(&path) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+        Some(data)
+    }
+
+    /// Write an entry, replacing any previous one
+    ///
+    /// Failures are logged rather than returned, as the cache is only an optimization.
+    pub fn put(&self, kind: CacheKind, key: &str, data: &[u8]) {
+        let replaced = match self.write(kind, key, data) {
+            Ok(replaced) => replaced,
+            Err(e) => {
+                log::warn!("Failed to write cache entry: {:#}", e);
+                return;
+            }
+        };
+        // The replaced entry no longer counts, saturating as the total is only an estimate
+        let update = |bytes: u64| (bytes + data.len() as u64).saturating_sub(replaced);
+        let before = self
+            .bytes
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bytes| {
+                Some(update(bytes))
+            })
+            .unwrap_or_else(|bytes| bytes);
+        if update(before) > self.max_bytes {
+            self.prune();
+        }
+    }
+
+    /// Write an entry, returning the size of the one it replaced
+    fn write(&self, kind: CacheKind, key: &str, data: &[u8]) -> Result<u64> {
+        let path = self.path(kind, key);
+        let dir = path
+            .parent()
+            .context("Cache entry has no parent directory")?;
+        fs::create_dir_all(dir)?;
+        let replaced = fs::metadata(&path).map_or(0, |meta| meta.len());
+
+        let tmp = dir.join(format!(".tmp-{}", uuid::Uuid::new_v4()));
+        let result = fs::File::create(&tmp)
+            .and_then(|mut file| file.write_all(data))
+            .and_then(|_| fs::rename(&tmp, &path));
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+        result?;
+        Ok(replaced)
+    }
+
+    /// Every entry with its size and last use
+    fn entries(&self) -> Vec<(PathBuf, u64, SystemTime)> {
+        let mut entries = Vec::new();
+        for kind in [CacheKind::Svg, CacheKind::Bitmap] {
+            let Ok(shards) = fs::read_dir(self.root.join(kind.dir_name())) else {
+                continue;
+            };
+            for shard in shards.flatten() {
+                let Ok(files) = fs::read_dir(shard.path()) else {
+                    continue;
+                };
+                for file in files.flatten() {
+                    let Ok(meta) = file.metadata() else {
+                        continue;
+                    };
+                    let modified = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                    entries.push((file.path(), meta.len(), modified));
+                }
+            }
+        }
+        entries
+    }
+
+    /// Remove the least recently used entries until the cache is down to 90% of its cap
+    pub fn prune(&self) {
+        let mut entries = self.entries();
+        let mut bytes: u64 = entries.iter().map(|(_, size, _)| size).sum();
+        let target = self.max_bytes / 10 * 9;
+
+        entries.sort_by_key(|(_, _, modified)| *modified);
+        for (path, size, _) in entries {
+            if bytes <= target {
+                break;
+            }
+            if fs::remove_file(&path).is_ok() {
+                bytes -= size;
+            }
+        }
+        self.bytes.store(bytes, Ordering::Relaxed);
+    }
+}
+
+/// Whether `dir` carries the marker written by [`DiskCache::open`]
+fn is_marked(dir: &Path) -> bool {
+    fs::read_to_string(dir.join(MARKER)).is_ok_and(|contents| contents == MARKER_CONTENTS)
+}
@@ -1,10 +1,84 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use crate::painter::PaintType;
+
 #[derive(Error, Debug)]
 pub enum SvgearError {
     #[error("HTTP error: {0}")]
     HttpError(#[from] reqwest::Error),
     #[error("SVG error: {0}")]
     SvgError(#[from] resvg::usvg::Error),
+    #[error("No paint backend registered for {0}")]
+    NoBackend(PaintType),
+    #[error("Node.js server exited")]
+    NodeExited,
+    #[error("Paint timed out after {0:?}")]
+    Timeout(Duration),
+    #[error("{0}")]
+    Paint(PaintError),
+    /// The error of a request shared by every concurrent identical request
+    #[error("{0:#}")]
+    Shared(Arc<anyhow::Error>),
     // Add more error types as needed
 }
+
+impl SvgearError {
+    /// Stable name of the error, reported to RPC and Emacs clients
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SvgearError::HttpError(_) => "http",
+            SvgearError::SvgError(_) => "svg",
+            SvgearError::NoBackend(_) => "no_backend",
+            SvgearError::NodeExited => "node_exited",
+            SvgearError::Timeout(_) => "timeout",
+            SvgearError::Paint(_) => "paint",
+            SvgearError::Shared(error) => SvgearError::kind_of(error).unwrap_or("shared"),
+        }
+    }
+
+    /// The [`SvgearError`] an error is, or is wrapped around, looking through shared errors
+    pub fn find(error: &anyhow::Error) -> Option<&SvgearError> {
+        match error.downcast_ref::<SvgearError>()? {
+            SvgearError::Shared(error) => SvgearError::find(error),
+            error => Some(error),
+        }
+    }
+
+    /// Kind of an error that is, or is wrapped around, an [`SvgearError`]
+    pub fn kind_of(error: &anyhow::Error) -> Option<&'static str> {
+        SvgearError::find(error).map(SvgearError::kind)
+    }
+}
+
+/// An error reported by a paint backend about the content it was given
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PaintError {
+    /// Human readable description, e.g. `Undefined control sequence \foo`
+    pub message: String,
+    /// What went wrong, e.g. `tex`, `mathml`, `asciimath` or `request`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    /// 1-based line in the content, when known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub line: Option<u32>,
+    /// 1-based column in the content, when known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub column: Option<u32>,
+}
+
+impl fmt::Display for PaintError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)?;
+        match (self.line, self.column) {
+            (Some(line), Some(column)) if line > 1 => write!(f, " at line {line} col {column}"),
+            (_, Some(column)) => write!(f, " at col {column}"),
+            (Some(line), None) => write!(f, " at line {line}"),
+            (None, None) => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use anyhow::Result;
+use resvg::usvg::{self, Transform};
+use serde::{Deserialize, Serialize};
+
+/// How an SVG is sized to the requested width and height
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Fit {
+    /// Scale each axis to the requested size, distorting the aspect ratio
+    #[default]
+    Stretch,
+    /// Scale to fit inside the requested size and center, leaving the rest
+    /// to the background
+    Contain,
+    /// Scale to fill the requested size and center, cropping the overflow
+    Cover,
+    /// Treat the width and height as upper bounds, shrinking to fit them
+    /// but never enlarging
+    Max,
+}
+
+/// Size of the image and where the SVG goes in it, in logical pixels
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Layout {
+    pub width: u32,
+    pub height: u32,
+    /// From SVG user space, the natural size of the SVG, to the image
+    pub transform: Transform,
+    /// Whether the SVG extends past the image and has to be clipped
+    pub clip: bool,
+}
+
+impl Fit {
+    /// Place an SVG of `natural` size for the requested `width` and `height`
+    ///
+    /// With only one of them given, the other follows the aspect ratio in
+    /// every mode but [`Fit::Max`], which still only shrinks.
+    pub fn layout(self, natural: usvg::Size, width: Option<u32>, height: Option<u32>) -> Layout {
+        let (nw, nh) = (natural.width(), natural.height());
+        // Never less than a pixel, which a very thin SVG would otherwise round down to
+        let stretch = |width: u32, height: u32| {
+            let (width, height) = (width.max(1), height.max(1));
+            Layout {
+                width,
+                height,
+                transform: Transform::from_scale(width as f32 / nw, height as f32 / nh),
+                clip: false,
+            }
+        };
+        match (self, width, height) {
+            (Fit::Max, ..) => {
+                let factor = [
+                    Some(1.0),
+                    width.map(|w| w as f32 / nw),
+                    height.map(|h| h as f32 / nh),
+                ]
+                .into_iter()
+                .flatten()
+                .fold(f32::INFINITY, f32::min);
+                stretch((nw * factor) as u32, (nh * factor) as u32)
+            }
+            (Fit::Contain | Fit::Cover, Some(w), Some(h)) => {
+                let (fx, fy) = (w as f32 / nw, h as f32 / nh);
+                let factor = if self == Fit::Contain {
+                    fx.min(fy)
+                } else {
+                    fx.max(fy)
+                };
+                Layout {
+                    width: w,
+                    height: h,
+                    transform: Transform::from_row(
+                        factor,
+                        0.0,
+                        0.0,
+                        factor,
+                        (w as f32 - nw * factor) / 2.0,
+                        (h as f32 - nh * factor) / 2.0,
+                    ),
+                    clip: self == Fit::Cover && fx != fy,
+                }
+            }
+            (_, Some(w), Some(h)) => stretch(w, h),
+            (_, Some(w), None) => stretch(w, (w as f32 * nh / nw) as u32),
+            (_, None, Some(h)) => stretch((h as f32 * nw / nh) as u32, h),
+            (_, None, None) => stretch(nw as u32, nh as u32),
+        }
+    }
+}
+
+impl Layout {
+    /// Whether the SVG fills the image, scaled without an offset
+    pub fn is_stretched(&self) -> bool {
+        self.transform.tx == 0.0 && self.transform.ty == 0.0 && !self.clip
+    }
+}
+
+impl std::fmt::Display for Fit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Fit::Stretch => f.write_str("stretch"),
+            Fit::Contain => f.write_str("contain"),
+            Fit::Cover => f.write_str("cover"),
+            Fit::Max => f.write_str("max"),
+        }
+    }
+}
+
+impl std::str::FromStr for Fit {
+    type Err = anyhow::Error;
+
+    /// Parse the names used by the CLI
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "stretch" => Ok(Fit::Stretch),
+            "contain" => Ok(Fit::Contain),
+            "cover" => Ok(Fit::Cover),
+            "max" => Ok(Fit::Max),
+            _ => Err(anyhow::anyhow!("Unsupported fit: {}", s)),
+        }
+    }
+}
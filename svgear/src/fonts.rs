@@ -0,0 +1,185 @@
+use anyhow::Result;
+use resvg::usvg::fontdb;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+
+/// Fonts available to `<text>` elements when rendering
+///
+/// SVGs painted by MathJax draw glyphs as paths and need no fonts, but
+/// Mermaid labels and hand written SVGs do. Text without a `font-family`
+/// and families that cannot be found fall back to the serif family.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct FontConfig {
+    /// Load the fonts installed on the system
+    #[serde(default = "default_system_fonts")]
+    pub system_fonts: bool,
+    /// Directories to load fonts from, recursively
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub font_dirs: Vec<PathBuf>,
+    /// Font files to load
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub font_files: Vec<PathBuf>,
+    /// Family used for `serif` and as the fallback, `Times New Roman` if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serif_family: Option<String>,
+    /// Family used for `sans-serif`, `Arial` if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sans_serif_family: Option<String>,
+    /// Family used for `monospace`, `Courier New` if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub monospace_family: Option<String>,
+}
+
+fn default_system_fonts() -> bool {
+    true
+}
+
+/// Fonts of the default [`FontConfig`], loaded on first use
+pub fn default_database() -> Arc<fontdb::Database> {
+    static DEFAULT: OnceLock<Arc<fontdb::Database>> = OnceLock::new();
+    DEFAULT
+        .get_or_init(|| Arc::new(FontConfig::default().build()))
+        .clone()
+}
+
+/// DejaVu Sans Mono, bundled so that error messages show without any fonts installed
+pub(crate) fn bundled_database() -> Arc<fontdb::Database> {
+    static BUNDLED: OnceLock<Arc<fontdb::Database>> = OnceLock::new();
+    BUNDLED
+        .get_or_init(|| {
+            let mut db = fontdb::Database::new();
+            db.load_font_data(include_bytes!("../fonts/DejaVuSansMono.ttf").to_vec());
+            Arc::new(db)
+        })
+        .clone()
+}
+
+/// Fonts of a [`FontConfig`], loaded the first time an SVG with text needs them
+///
+/// Loading the system fonts takes a while and most SVGs, such as those
+/// painted by MathJax, have no text. Clones share the loaded fonts.
+#[derive(Clone, Default)]
+pub(crate) struct LazyFonts {
+    config: FontConfig,
+    loaded: Arc<OnceLock<Arc<fontdb::Database>>>,
+}
+
+impl LazyFonts {
+    /// Fonts of `config`, whose paths are checked now but loaded later
+    pub(crate) fn new(config: FontConfig) -> Result<Self> {
+        config.check()?;
+        Ok(LazyFonts {
+            config,
+            loaded: Arc::default(),
+        })
+    }
+
+    pub(crate) fn config(&self) -> &FontConfig {
+        &self.config
+    }
+
+    /// The fonts to parse `svg` with, loading them if it has text
+    pub(crate) fn for_svg(&self, svg: &str) -> Arc<fontdb::Database> {
+        if svg.contains("<text") {
+            self.get()
+        } else {
+            static EMPTY: OnceLock<Arc<fontdb::Database>> = OnceLock::new();
+            EMPTY.get_or_init(Arc::default).clone()
+        }
+    }
+
+    /// The fonts, loading them on first use
+    pub(crate) fn get(&self) -> Arc<fontdb::Database> {
+        self.loaded
+            .get_or_init(|| self.config.load_checked())
+            .clone()
+    }
+}
+
+impl std::fmt::Debug for LazyFonts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LazyFonts")
+            .field("config", &self.config)
+            .field("faces", &self.loaded.get().map(|db| db.len()))
+            .finish()
+    }
+}
+
+impl Default for FontConfig {
+    /// The system fonts with the default families
+    fn default() -> Self {
+        FontConfig {
+            system_fonts: true,
+            font_dirs: Vec::new(),
+            font_files: Vec::new(),
+            serif_family: None,
+            sans_serif_family: None,
+            monospace_family: None,
+        }
+    }
+}
+
+impl FontConfig {
+    /// Whether this is the configuration used when none is given
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Load the configured fonts
+    ///
+    /// The database for the default configuration is loaded once and shared.
+    pub fn load(&self) -> Result<Arc<fontdb::Database>> {
+        self.check()?;
+        Ok(self.load_checked())
+    }
+
+    fn load_checked(&self) -> Arc<fontdb::Database> {
+        if self.is_default() {
+            default_database()
+        } else {
+            Arc::new(self.build())
+        }
+    }
+
+    /// Check that the configured font directories and files exist
+    pub fn check(&self) -> Result<()> {
+        for dir in &self.font_dirs {
+            if !dir.is_dir() {
+                anyhow::bail!("Font directory not found: {}", dir.display());
+            }
+        }
+        for file in &self.font_files {
+            if !file.is_file() {
+                anyhow::bail!("Font file not found: {}", file.display());
+            }
+        }
+        Ok(())
+    }
+
+    fn build(&self) -> fontdb::Database {
+        let mut db = fontdb::Database::new();
+        if self.system_fonts {
+            db.load_system_fonts();
+        }
+        for dir in &self.font_dirs {
+            db.load_fonts_dir(dir);
+        }
+        for file in &self.font_files {
+            if let Err(e) = db.load_font_file(file) {
+                log::warn!("Failed to load font {}: {}", file.display(), e);
+            }
+        }
+        if let Some(family) = &self.serif_family {
+            db.set_serif_family(family);
+        }
+        if let Some(family) = &self.sans_serif_family {
+            db.set_sans_serif_family(family);
+        }
+        if let Some(family) = &self.monospace_family {
+            db.set_monospace_family(family);
+        }
+        log::debug!("loaded {} font faces", db.len());
+        db
+    }
+}
@@ -0,0 +1,156 @@
+use anyhow::Result;
+use resvg::tiny_skia;
+use serde::{Deserialize, Serialize};
+
+/// JPEG quality used when none is given
+pub const DEFAULT_JPEG_QUALITY: u8 = 90;
+
+/// Encoding of [`Bitmap`](crate::manager::Bitmap) data
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ImageFormat {
+    #[default]
+    Png,
+    /// Lossless WebP, usually smaller than PNG
+    Webp,
+    /// JPEG at [`RenderOptions::quality`](crate::RenderOptions::quality),
+    /// transparent pixels blended onto white
+    Jpeg,
+    /// The Quite OK Image format, fast to encode and decode
+    Qoi,
+    /// Raw RGBA rows with straight alpha, 4 bytes per pixel
+    Rgba,
+    /// Raw RGBA rows with premultiplied alpha, as tiny-skia and Cairo use them
+    RgbaPremultiplied,
+    /// A single page PDF keeping the SVG as vector graphics, see [`crate::pdf`]
+    Pdf,
+    /// The SVG re-serialized without external references, see [`crate::normalize`]
+    NormalizedSvg,
+}
+
+impl ImageFormat {
+    /// Whether the data is raw pixels rather than an image file
+    pub fn is_raw(&self) -> bool {
+        matches!(self, ImageFormat::Rgba | ImageFormat::RgbaPremultiplied)
+    }
+
+    /// Whether the data is written from the SVG tree as vector graphics rather than pixels
+    pub fn is_vector(&self) -> bool {
+        matches!(self, ImageFormat::Pdf | ImageFormat::NormalizedSvg)
+    }
+
+    /// Bytes per row of raw pixel data, if the format is raw
+    pub fn stride(&self, width: u32) -> Option<u32> {
+        self.is_raw().then_some(width * 4)
+    }
+
+    /// Check a quality setting, which only JPEG has
+    pub(crate) fn check_quality(&self, quality: Option<u8>) -> Result<()> {
+        match (self, quality) {
+            (_, None) => Ok(()),
+            (ImageFormat::Jpeg, Some(quality)) if !(1..=100).contains(&quality) => {
+                anyhow::bail!("Invalid JPEG quality {}, must be from 1 to 100", quality)
+            }
+            (ImageFormat::Jpeg, Some(_)) => Ok(()),
+            (_, Some(_)) => anyhow::bail!("{} has no quality setting, only JPEG has", self),
+        }
+    }
+
+    /// Encode a rendered pixmap
+    pub(crate) fn encode(
+        &self,
+        pixmap: &tiny_skia::Pixmap,
+        quality: Option<u8>,
+    ) -> Result<Vec<u8>> {
+        let (width, height) = (pixmap.width(), pixmap.height());
+        let data = match self {
+            ImageFormat::Png => pixmap.encode_png()?,
+            ImageFormat::Webp => {
+                let mut data = Vec::new();
+                image_webp::WebPEncoder::new(&mut data).encode(
+                    &straight_rgba(pixmap),
+                    width,
+                    height,
+                    image_webp::ColorType::Rgba8,
+                )?;
+                data
+            }
+            ImageFormat::Jpeg => {
+                self.check_quality(quality)?;
+                let quality = quality.unwrap_or(DEFAULT_JPEG_QUALITY);
+                let (Ok(w), Ok(h)) = (u16::try_from(width), u16::try_from(height)) else {
+                    anyhow::bail!("JPEG images are limited to 65535x65535 pixels");
+                };
+                // Premultiplied colors over white are the color plus the missing coverage
+                let rgb: Vec<u8> = pixmap
+                    .data()
+                    .chunks_exact(4)
+                    .flat_map(|p| {
+                        let cover = 255 - p[3];
+                        [p[0] + cover, p[1] + cover, p[2] + cover]
+                    })
+                    .collect();
+                let mut data = Vec::new();
+                jpeg_encoder::Encoder::new(&mut data, quality).encode(
+                    &rgb,
+                    w,
+                    h,
+                    jpeg_encoder::ColorType::Rgb,
+                )?;
+                data
+            }
+            ImageFormat::Qoi => qoi::encode_to_vec(straight_rgba(pixmap), width, height)?,
+            ImageFormat::Rgba => straight_rgba(pixmap),
+            ImageFormat::RgbaPremultiplied => pixmap.data().to_vec(),
+            ImageFormat::Pdf | ImageFormat::NormalizedSvg => {
+                anyhow::bail!("{} is written from the SVG tree, not a pixmap", self)
+            }
+        };
+        Ok(data)
+    }
+}
+
+impl std::fmt::Display for ImageFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImageFormat::Png => f.write_str("png"),
+            ImageFormat::Webp => f.write_str("webp"),
+            ImageFormat::Jpeg => f.write_str("jpeg"),
+            ImageFormat::Qoi => f.write_str("qoi"),
+            ImageFormat::Rgba => f.write_str("rgba"),
+            ImageFormat::RgbaPremultiplied => f.write_str("rgba-premultiplied"),
+            ImageFormat::Pdf => f.write_str("pdf"),
+            ImageFormat::NormalizedSvg => f.write_str("normalized-svg"),
+        }
+    }
+}
+
+impl std::str::FromStr for ImageFormat {
+    type Err = anyhow::Error;
+
+    /// Parse the names used by the CLI
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "png" => Ok(ImageFormat::Png),
+            "webp" => Ok(ImageFormat::Webp),
+            "jpeg" | "jpg" => Ok(ImageFormat::Jpeg),
+            "qoi" => Ok(ImageFormat::Qoi),
+            "rgba" => Ok(ImageFormat::Rgba),
+            "rgba-premultiplied" => Ok(ImageFormat::RgbaPremultiplied),
+            "pdf" => Ok(ImageFormat::Pdf),
+            "normalized-svg" => Ok(ImageFormat::NormalizedSvg),
+            _ => Err(anyhow::anyhow!("Unsupported output type: {}", s)),
+        }
+    }
+}
+
+fn straight_rgba(pixmap: &tiny_skia::Pixmap) -> Vec<u8> {
+    pixmap
+        .pixels()
+        .iter()
+        .flat_map(|p| {
+            let c = p.demultiply();
+            [c.red(), c.green(), c.blue(), c.alpha()]
+        })
+        .collect()
+}
@@ -0,0 +1,160 @@
+use anyhow::Result;
+use resvg::tiny_skia::{self, PathBuilder};
+
+use crate::manager::RenderOptions;
+use crate::metrics::EX_TO_PX;
+
+/// Pixels per `em`, the default font size of usvg
+pub const EM_TO_PX: f32 = 2.0 * EX_TO_PX;
+
+/// Border width used when a border color is given without one
+pub const DEFAULT_BORDER_WIDTH: f32 = 1.0;
+
+/// Margin, padding, background and border drawn around the SVG, in logical pixels
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Frame {
+    /// Transparent space between the border and each edge of the image
+    pub margin: f32,
+    /// Space around the SVG inside the margin, which the border is drawn over
+    pub padding: f32,
+    /// Fill of the image inside the margin, transparent if unset
+    pub background: Option<tiny_skia::Color>,
+    /// Color and width of a line inside the margin
+    pub border: Option<(tiny_skia::Color, f32)>,
+    /// Radius of the rounded corners of the background and border
+    pub radius: f32,
+}
+
+impl Frame {
+    /// Parse the frame from render options
+    pub fn from_options(options: &RenderOptions) -> Result<Self> {
+        let margin = options
+            .margin
+            .as_deref()
+            .map(parse_length)
+            .transpose()?
+            .unwrap_or(0.0);
+        let padding = options
+            .padding
+            .as_deref()
+            .map(parse_length)
+            .transpose()?
+            .unwrap_or(0.0);
+        let radius = options
+            .border_radius
+            .as_deref()
+            .map(parse_length)
+            .transpose()?
+            .unwrap_or(0.0);
+        let background = options.background.as_deref().map(parse_color).transpose()?;
+        let border = options.border.as_deref().map(parse_color).transpose()?;
+        let border_width = options.border_width.unwrap_or(DEFAULT_BORDER_WIDTH);
+        if !(border_width.is_finite() && border_width >= 0.0) {
+            anyhow::bail!(
+                "Invalid border width {}, must not be negative",
+                border_width
+            );
+        }
+
+        Ok(Frame {
+            margin,
+            padding,
+            background: background.map(to_skia_color),
+            border: border.map(|c| (to_skia_color(c), border_width)),
+            radius,
+        })
+    }
+
+    /// Distance from each edge of the image to the SVG
+    pub fn offset(&self) -> f32 {
+        self.margin + self.padding
+    }
+
+    /// Size of the image holding content of `width` by `height`
+    pub fn size(&self, width: f32, height: f32) -> (f32, f32) {
+        (width + 2.0 * self.offset(), height + 2.0 * self.offset())
+    }
+
+    /// Outline of the background in an image of `width` by `height`
+    ///
+    /// This is the edge of the image inset by the margin, rounded like the
+    /// outer edge of the border.
+    pub fn background_outline(&self, width: f32, height: f32) -> Option<tiny_skia::Path> {
+        let border_width = self.border.map_or(0.0, |(_, w)| w);
+        let radius = if self.radius > 0.0 {
+            self.radius + border_width / 2.0
+        } else {
+            0.0
+        };
+        rounded_rect(width, height, self.margin, radius)
+    }
+
+    /// Center line of the border in an image of `width` by `height`, if there is a border
+    ///
+    /// This is the edge of the image inset by the margin and half the border
+    /// width, so that the border stays inside the margin. The inset stops at
+    /// the middle of the image, where a border too wide for it covers all of it.
+    pub fn border_outline(&self, width: f32, height: f32) -> Option<tiny_skia::Path> {
+        let (_, border_width) = self.border?;
+        rounded_rect(width, height, self.margin + border_width / 2.0, self.radius)
+    }
+}
+
+/// A rectangle of `width` by `height` inset by `inset`, with corners of `radius`
+///
+/// The inset is clamped to just under half of each side, leaving a sliver
+/// for a wide border to be stroked along, and the radius to half of what remains.
+fn rounded_rect(width: f32, height: f32, inset: f32, radius: f32) -> Option<tiny_skia::Path> {
+    let (dx, dy) = (inset.min(width * 0.499), inset.min(height * 0.499));
+    let rect = tiny_skia::Rect::from_ltrb(dx, dy, width - dx, height - dy)?;
+    let radius = radius.min(rect.width() / 2.0).min(rect.height() / 2.0);
+    if radius <= 0.0 {
+        return Some(PathBuilder::from_rect(rect));
+    }
+
+    // Quarter circles as cubic curves, with control points this far along the tangents
+    let k = radius * (1.0 - 0.552_284_8);
+    let (l, t, r, b) = (rect.left(), rect.top(), rect.right(), rect.bottom());
+    let mut pb = PathBuilder::new();
+    pb.move_to(l + radius, t);
+    pb.line_to(r - radius, t);
+    pb.cubic_to(r - k, t, r, t + k, r, t + radius);
+    pb.line_to(r, b - radius);
+    pb.cubic_to(r, b - k, r - k, b, r - radius, b);
+    pb.line_to(l + radius, b);
+    pb.cubic_to(l + k, b, l, b - k, l, b - radius);
+    pb.line_to(l, t + radius);
+    pb.cubic_to(l, t + k, l + k, t, l + radius, t);
+    pb.close();
+    pb.finish()
+}
+
+/// Parse a CSS color such as `#dcdccc` or `white`
+pub(crate) fn parse_color(color: &str) -> Result<svgtypes::Color> {
+    color
+        .parse()
+        .map_err(|e| anyhow::anyhow!("Invalid color {:?}: {}", color, e))
+}
+
+fn to_skia_color(c: svgtypes::Color) -> tiny_skia::Color {
+    tiny_skia::Color::from_rgba8(c.red, c.green, c.blue, c.alpha)
+}
+
+/// Parse a length in `px`, `em` or `ex`, pixels if it has no unit
+fn parse_length(value: &str) -> Result<f32> {
+    let trimmed = value.trim();
+    let (number, factor) = if let Some(em) = trimmed.strip_suffix("em") {
+        (em, EM_TO_PX)
+    } else if let Some(ex) = trimmed.strip_suffix("ex") {
+        (ex, EX_TO_PX)
+    } else {
+        (trimmed.strip_suffix("px").unwrap_or(trimmed), 1.0)
+    };
+    match number.trim().parse::<f32>() {
+        Ok(v) if v.is_finite() && v >= 0.0 => Ok(v * factor),
+        _ => Err(anyhow::anyhow!(
+            "Invalid length {:?}, expected e.g. 4px or 0.5em",
+            value
+        )),
+    }
+}
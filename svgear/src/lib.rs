@@ -1,37 +1,59 @@
 pub mod client;
+pub mod disk_cache;
 pub mod error;
+pub mod fit;
+pub mod fonts;
+pub mod format;
+pub mod frame;
 pub mod manager;
+pub mod metrics;
+pub mod normalize;
 pub mod painter;
+pub mod pdf;
 pub mod rpc;
-
-use std::sync::Arc;
+pub mod single_flight;
+pub mod trim;
 
 pub use client::SvgClient;
+pub use disk_cache::DiskCache;
+pub use fit::Fit;
+pub use fonts::FontConfig;
+pub use format::ImageFormat;
+pub use frame::Frame;
 pub use manager::{
-    GetBitmapRequest, GetBitmapResponse, RenderRequest, RenderResponse, SharedSvgManager,
-    SvgManager,
+    CacheLimit, CacheLimits, CacheStats, GetBitmapRequest, GetBitmapResponse, RenderOptions,
+    RenderRequest, RenderResponse, Renderer, SharedSvgManager, SvgManager,
 };
-pub use painter::{PaintParams, PaintType, Painter};
-pub use rpc::{Method, PaintResult, RenderToBitmapParams, RpcRequest, RpcResponse, RpcServer};
-use tokio::{
-    runtime::{Builder, Runtime},
-    sync::RwLock,
+pub use metrics::SvgMetrics;
+pub use painter::{PaintBackend, PaintParams, PaintType, Painter};
+pub use rpc::{
+    FontsResult, Method, PaintResult, RenderToBitmapParams, RpcRequest, RpcResponse, RpcServer,
 };
 pub use tokio;
+pub use trim::{Trim, TrimOffset};
 
 #[derive(Debug)]
 pub struct Svgear {
-    pub manager: SvgManager,
+    pub manager: SharedSvgManager,
     pub painter: Painter,
 }
 
-
 impl Svgear {
     pub fn new(exe_path: String) -> Self {
+        Self::with_workers(exe_path, painter::DEFAULT_WORKERS)
+    }
+
+    /// Create with a pool of `workers` Node processes
+    pub fn with_workers(exe_path: String, workers: usize) -> Self {
         Svgear {
-            manager: SvgManager::new(),
-            painter: Painter::with_node_server(exe_path),
+            manager: SharedSvgManager::new(),
+            painter: Painter::with_node_pool(exe_path, workers),
         }
     }
-}
 
+    /// Share painted SVGs and rendered bitmaps through a cache on disk
+    pub fn set_disk_cache(&mut self, disk_cache: Option<DiskCache>) {
+        self.manager.set_disk_cache(disk_cache.clone());
+        self.painter.set_disk_cache(disk_cache);
+    }
+}
@@ -1,11 +1,15 @@
 use std::fs;
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use anyhow::{Context, Result};
-use clap::{Parser, Subcommand};
-use svgear::painter::{NodeServer, PaintParams};
-use svgear::{PaintType, Painter, RenderRequest, RpcServer, SharedSvgManager};
+use clap::{Args, Parser, Subcommand};
+use svgear::disk_cache::DEFAULT_MAX_BYTES;
+use svgear::painter::{PaintParams, DEFAULT_WORKERS};
+use svgear::{
+    CacheLimit, CacheLimits, DiskCache, Fit, FontConfig, ImageFormat, PaintType, Painter,
+    RenderOptions, RenderRequest, RpcServer, SharedSvgManager, Trim,
+};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -14,30 +18,131 @@ struct Cli {
     command: Commands,
     #[arg(short, long)]
     exe_path: String,
+    /// Directory to keep painted SVGs and rendered bitmaps in across runs
+    #[arg(long, global = true)]
+    cache_dir: Option<String>,
+    /// Size cap of the cache directory in bytes
+    #[arg(long, global = true, default_value_t = DEFAULT_MAX_BYTES)]
+    cache_max_bytes: u64,
+    #[command(flatten)]
+    fonts: FontArgs,
+}
+
+/// Fonts for `<text>` elements, e.g. in Mermaid diagrams
+#[derive(Args)]
+struct FontArgs {
+    /// Do not load the fonts installed on the system
+    #[arg(long, global = true)]
+    no_system_fonts: bool,
+    /// Directory to load fonts from, can be repeated
+    #[arg(long, global = true)]
+    font_dir: Vec<PathBuf>,
+    /// Font file to load, can be repeated
+    #[arg(long, global = true)]
+    font_file: Vec<PathBuf>,
+    /// Family used for `serif` text and as the fallback
+    #[arg(long, global = true)]
+    serif_family: Option<String>,
+    /// Family used for `sans-serif` text
+    #[arg(long, global = true)]
+    sans_serif_family: Option<String>,
+    /// Family used for `monospace` text
+    #[arg(long, global = true)]
+    monospace_family: Option<String>,
+}
+
+impl From<FontArgs> for FontConfig {
+    fn from(args: FontArgs) -> Self {
+        FontConfig {
+            system_fonts: !args.no_system_fonts,
+            font_dirs: args.font_dir,
+            font_files: args.font_file,
+            serif_family: args.serif_family,
+            sans_serif_family: args.sans_serif_family,
+            monospace_family: args.monospace_family,
+        }
+    }
 }
 
 #[derive(Subcommand)]
+#[allow(clippy::large_enum_variant)]
 enum Commands {
     /// Generate SVG from MathJax or mermaid
     Render {
         /// input content
         input: String,
+        /// svg, mermaid, inlinetex, equation, inlinemathml, mathml, inlineasciimath or asciimath
         #[arg(short, long, default_value = "inlinetex")]
         input_type: String,
+        /// svg, normalized-svg, png, webp, jpeg, qoi, rgba, rgba-premultiplied or pdf
         #[arg(short = 'o', long, default_value = "svg")]
         output_type: String,
+        /// quality of JPEG output, from 1 to 100
+        #[arg(long)]
+        quality: Option<u8>,
+        /// convert text to paths in normalized-svg output
+        #[arg(long)]
+        text_to_paths: bool,
         #[arg(long)]
         width: Option<u32>,
         #[arg(long)]
         height: Option<u32>,
+        /// stretch, contain, cover or max, how to size to both width and height
+        #[arg(long)]
+        fit: Option<Fit>,
+        /// bounds or transparent, render only the drawn area of the SVG
+        #[arg(long)]
+        trim: Option<Trim>,
         /// file location for output
         #[arg(short = 'O', long)]
         output: Option<String>,
+        /// give up painting after this many milliseconds
+        #[arg(long)]
+        timeout: Option<u64>,
+        /// color for `currentColor` in the PNG, e.g. "#dcdccc"
+        #[arg(long)]
+        foreground: Option<String>,
+        /// background color of the PNG, transparent by default
+        #[arg(long)]
+        background: Option<String>,
+        /// transparent space outside the border, e.g. "4px" or "0.25em"
+        #[arg(long)]
+        margin: Option<String>,
+        /// space around the image, e.g. "4px" or "0.25em"
+        #[arg(long)]
+        padding: Option<String>,
+        /// color of a border around the image
+        #[arg(long)]
+        border: Option<String>,
+        /// width of the border in pixels, 1 by default
+        #[arg(long)]
+        border_width: Option<f32>,
+        /// radius of rounded corners, e.g. "4px" or "0.25em"
+        #[arg(long)]
+        border_radius: Option<String>,
+        /// device pixels per logical pixel, e.g. 2 for HiDPI screens
+        #[arg(long)]
+        scale: Option<f32>,
     },
     /// Run in server mode
     Serve {
         #[arg(short, long, default_value = "3000")]
         port: u16,
+        /// Number of Node.js worker processes, each running MathJax, 1 by default
+        #[arg(short, long)]
+        workers: Option<usize>,
+        /// Memory budget for cached SVG sources in bytes, 16 MiB by default
+        #[arg(long)]
+        max_svg_bytes: Option<usize>,
+        /// Maximum number of cached SVG sources
+        #[arg(long)]
+        max_svgs: Option<usize>,
+        /// Memory budget for cached bitmaps in bytes, 64 MiB by default
+        #[arg(long)]
+        max_bitmap_bytes: Option<usize>,
+        /// Maximum number of cached bitmaps
+        #[arg(long)]
+        max_bitmaps: Option<usize>,
     },
 }
 
@@ -52,9 +157,19 @@ fn get_input_content(input: &str) -> Result<String> {
     }
 }
 
-pub async fn run_server(port: u16, exe_path: String) -> anyhow::Result<()> {
-    let manager = SharedSvgManager::new();
-    let painter = Painter::with_node_server(exe_path);
+pub async fn run_server(
+    port: u16,
+    exe_path: String,
+    workers: usize,
+    limits: CacheLimits,
+    disk_cache: Option<DiskCache>,
+    fonts: FontConfig,
+) -> anyhow::Result<()> {
+    let manager = SharedSvgManager::with_limits(limits);
+    manager.set_disk_cache(disk_cache.clone());
+    manager.set_fonts(fonts)?;
+    let mut painter = Painter::with_node_pool(exe_path, workers);
+    painter.set_disk_cache(disk_cache);
     let server = RpcServer::new(manager, painter);
     server.start(port).await
 }
@@ -63,45 +178,87 @@ pub async fn run_server(port: u16, exe_path: String) -> anyhow::Result<()> {
 async fn main() -> Result<()> {
     env_logger::init();
     let cli = Cli::parse();
+    let disk_cache = cli
+        .cache_dir
+        .map(|dir| DiskCache::open(dir, cli.cache_max_bytes))
+        .transpose()?;
+    let fonts = FontConfig::from(cli.fonts);
 
     match cli.command {
         Commands::Render {
             input,
             input_type,
             output_type,
+            quality,
+            text_to_paths,
             width,
             height,
+            fit,
+            trim,
             output,
+            timeout,
+            foreground,
+            background,
+            margin,
+            padding,
+            border,
+            border_width,
+            border_radius,
+            scale,
         } => {
+            // Anything but SVG is rendered to a bitmap
+            let format = match output_type.as_str() {
+                "svg" => None,
+                other => Some(other.parse::<ImageFormat>()?),
+            };
+            let options = RenderOptions {
+                foreground,
+                background,
+                margin,
+                padding,
+                border,
+                border_width,
+                border_radius,
+                scale,
+                fit,
+                trim,
+                format,
+                quality,
+                text_to_paths,
+            };
+
             // Get content from input string or file
-            let content = match input_type.as_str() {
-                "inlinetex" => input.clone(),    // Use directly for inline TeX
-                _ => get_input_content(&input)?, // Try to load from file for others
+            let content = match input_type.parse::<PaintType>() {
+                Ok(ty) if ty.is_inline() => input.clone(), // Use directly for inline math
+                _ => get_input_content(&input)?,           // Try to load from file for others
             };
 
             match input_type.as_str() {
                 "svg" => {
                     // Direct SVG rendering
                     let mut manager = svgear::SvgManager::new();
+                    manager.set_disk_cache(disk_cache);
+                    manager.set_fonts(fonts)?;
                     let resp = manager.process_render_request(RenderRequest {
                         svg_data: content.clone(),
                         width,
                         height,
                         id: None,
+                        options,
                     })?;
 
                     // Output based on requested format
-                    if output_type == "png" {
+                    if let Some(format) = format {
                         // Write bitmap data to file or stdout
                         if let Some(output) = output {
                             fs::write(&output, &resp.bitmap.data)
                                 .context("Failed to write output file")?;
-                            println!("Saved PNG to {}", output);
+                            println!("Saved {} to {}", format, output);
                         } else {
                             let mut stdout = std::io::stdout();
                             stdout.write_all(&resp.bitmap.data)?;
                         }
-                    } else if output_type == "svg" {
+                    } else {
                         // Write SVG to file or stdout
                         if let Some(output) = output {
                             fs::write(&output, content).context("Failed to write output file")?;
@@ -111,65 +268,80 @@ async fn main() -> Result<()> {
                         }
                     }
                 }
-                "mermaid" | "inlinetex" | "equation" => {
+                _ => {
                     // Create a painter with MathJax server
-                    // let node_server = NodeServer::new(cli.exe_path);
-                    let painter = Painter::with_node_server(cli.exe_path);
+                    let mut painter = Painter::with_node_server(cli.exe_path);
+                    painter.set_disk_cache(disk_cache.clone());
 
                     // Determine paint type
-                    let paint_type = match input_type.as_str() {
-                        "mermaid" => PaintType::Mermaid,
-                        "inlinetex" => PaintType::InlineTeX,
-                        "equation" => PaintType::Equation,
-                        _ => unreachable!(),
-                    };
+                    let paint_type: PaintType = input_type.parse()?;
 
                     // Create paint params
                     let params = PaintParams {
                         content,
                         ty: paint_type,
+                        timeout_ms: timeout,
                     };
 
                     // Paint to SVG
                     let svg_content = painter.paint(params).await?;
 
-                    if output_type == "svg" {
-                        // Output SVG directly
-                        if let Some(output) = output {
-                            fs::write(&output, svg_content)
-                                .context("Failed to write output file")?;
-                            println!("Saved SVG to {}", output);
-                        } else {
-                            println!("{}", svg_content);
-                        }
-                    } else if output_type == "png" {
+                    if let Some(format) = format {
                         // Render SVG to bitmap
                         let mut manager = svgear::SvgManager::new();
+                        manager.set_disk_cache(disk_cache);
+                        manager.set_fonts(fonts)?;
                         let resp = manager.process_render_request(RenderRequest {
                             svg_data: svg_content,
                             width,
                             height,
                             id: None,
+                            options,
                         })?;
 
                         // Write bitmap
                         if let Some(output) = output {
                             fs::write(&output, &resp.bitmap.data)
                                 .context("Failed to write output file")?;
-                            println!("Saved PNG to {}", output);
+                            println!("Saved {} to {}", format, output);
                         } else {
                             let mut stdout = std::io::stdout();
                             stdout.write_all(&resp.bitmap.data)?;
                         }
+                    } else {
+                        // Output SVG directly
+                        if let Some(output) = output {
+                            fs::write(&output, svg_content)
+                                .context("Failed to write output file")?;
+                            println!("Saved SVG to {}", output);
+                        } else {
+                            println!("{}", svg_content);
+                        }
                     }
                 }
-                _ => {
-                    return Err(anyhow::anyhow!("Unsupported input type: {}", input_type));
-                }
             }
         }
-        Commands::Serve { port } => {
-            run_server(port, cli.exe_path).await?;
+        Commands::Serve {
+            port,
+            workers,
+            max_svg_bytes,
+            max_svgs,
+            max_bitmap_bytes,
+            max_bitmaps,
+        } => {
+            let workers = workers.unwrap_or(DEFAULT_WORKERS);
+            let defaults = CacheLimits::default();
+            let limits = CacheLimits {
+                svgs: CacheLimit {
+                    max_bytes: max_svg_bytes.or(defaults.svgs.max_bytes),
+                    max_entries: max_svgs,
+                },
+                bitmaps: CacheLimit {
+                    max_bytes: max_bitmap_bytes.or(defaults.bitmaps.max_bytes),
+                    max_entries: max_bitmaps,
+                },
+            };
+            run_server(port, cli.exe_path, workers, limits, disk_cache, fonts).await?;
         }
     }
 
@@ -1,30 +1,101 @@
 use anyhow::Result;
 use fxhash::FxHashMap;
-use resvg::{
-    tiny_skia,
-    usvg::{self, Tree},
-};
+use resvg::{tiny_skia, usvg};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
+use std::ops::ControlFlow;
 use std::sync::{Arc, RwLock};
 
+use crate::disk_cache::{CacheKind, DiskCache};
+use crate::fit::{Fit, Layout};
+use crate::fonts::{self, FontConfig, LazyFonts};
+use crate::format::ImageFormat;
+use crate::frame::{parse_color, Frame};
+use crate::metrics::SvgMetrics;
+use crate::single_flight::SingleFlight;
+use crate::trim::{Trim, TrimOffset};
+use crate::{normalize, pdf};
+
+pub(crate) mod cache;
+
+use cache::LruCache;
+pub use cache::{CacheLimit, CacheLimits, CacheStats, CacheUsage};
+
 /// Represents a request to render an SVG
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RenderRequest {
     /// SVG content to render
     pub svg_data: String,
-    /// Desired width for rendering
+    /// Desired width for rendering, in logical pixels
     pub width: Option<u32>,
-    /// Desired height for rendering
+    /// Desired height for rendering, in logical pixels
     pub height: Option<u32>,
     /// Optional ID to use instead of auto-generated hash
     pub id: Option<String>,
+    /// Colors, frame, size fitting, scale and format to render with
+    #[serde(flatten)]
+    pub options: RenderOptions,
+}
+
+/// Options that change how an SVG is rendered, and so which bitmap is cached
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RenderOptions {
+    /// CSS color that `currentColor` resolves to, black if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub foreground: Option<String>,
+    /// CSS color to fill the background with, transparent if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub background: Option<String>,
+    /// Transparent space outside the border, as a length like the padding
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub margin: Option<String>,
+    /// Space around the SVG, in pixels such as `4` or `4px`, or in `em`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub padding: Option<String>,
+    /// CSS color of a border along the edges, none if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub border: Option<String>,
+    /// Width of the border in pixels, 1 if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub border_width: Option<f32>,
+    /// Corner radius of the background and border, as a length like the padding
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub border_radius: Option<String>,
+    /// Device pixels per logical pixel, e.g. 2 on HiDPI screens, 1 if unset
+    ///
+    /// The logical size is the natural size of the SVG or the requested
+    /// width and height; the bitmap has that size times the scale. Vector
+    /// formats keep the logical size.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scale: Option<f32>,
+    /// How the SVG is sized when both width and height are requested, stretched if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fit: Option<Fit>,
+    /// Render only the area of the SVG that is drawn on, untrimmed if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trim: Option<Trim>,
+    /// Encoding of the bitmap data, PNG if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<ImageFormat>,
+    /// JPEG quality from 1 to 100, 90 if unset, and an error with other formats
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quality: Option<u8>,
+    /// Convert text to paths in normalized SVGs, so that they need no fonts
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub text_to_paths: bool,
+}
+
+impl RenderOptions {
+    /// Whether these are the options a plain render uses
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
 }
 
 /// Response from rendering an SVG
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RenderResponse {
-    /// ID of the rendered SVG (either provided or generated)
+    /// ID of the rendered bitmap, see [`SvgManager::bitmap_id`]
     pub id: String,
     /// Whether the SVG was newly rendered or retrieved from cache
     pub cached: bool,
@@ -35,47 +106,140 @@ pub struct RenderResponse {
 /// Represents a request to retrieve a rendered bitmap
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GetBitmapRequest {
-    /// ID of the SVG to retrieve
+    /// ID of the bitmap to retrieve, or of an SVG for its latest bitmap
     pub id: String,
 }
 
 /// Bitmap structure to hold rendered image data and dimensions
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Bitmap {
-    /// The rendered bitmap as PNG bytes (base64 encoded when serialized)
+    /// The rendered bitmap encoded as `format` (base64 encoded when serialized)
     #[serde(with = "serde_bytes")]
     pub data: Vec<u8>,
-    /// Width of the bitmap
+    /// Encoding of `data`
+    #[serde(default)]
+    pub format: ImageFormat,
+    /// Bytes per row of raw RGBA data, unset for image files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stride: Option<u32>,
+    /// Width of the bitmap in device pixels
     pub width: u32,
-    /// Height of the bitmap
+    /// Height of the bitmap in device pixels
     pub height: u32,
+    /// Width to display the bitmap at, before [`RenderOptions::scale`]
+    pub logical_width: u32,
+    /// Height to display the bitmap at, before [`RenderOptions::scale`]
+    pub logical_height: u32,
+    /// Baseline metrics scaled to the bitmap, if the SVG declares them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<SvgMetrics>,
+    /// Where the trimmed area starts in the SVG, if [`RenderOptions::trim`] cut anything off
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trim_offset: Option<TrimOffset>,
 }
 
 /// Response containing a rendered bitmap
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GetBitmapResponse {
-    /// ID of the SVG
+    /// ID the bitmap was requested with
     pub id: String,
     /// The bitmap data and dimensions
     #[serde(flatten)]
     pub bitmap: Bitmap,
+    /// Set when the bitmap is an error image standing in for content that failed to paint
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub paint_error: Option<String>,
 }
 
 /// Manager for SVG storage and rendering
+///
+/// An SVG can be rendered to several bitmaps, one per size and set of
+/// options, each stored under its own [`SvgManager::bitmap_id`]. SVGs and
+/// bitmaps are evicted least recently used first to stay within the
+/// [`CacheLimits`].
 #[derive(Debug, Clone)]
 pub struct SvgManager {
     /// Storage for original SVG data
-    svgs: FxHashMap<String, String>,
-    /// Storage for rendered bitmaps with their metadata
-    bitmaps: FxHashMap<String, Bitmap>,
+    svgs: LruCache<String>,
+    /// Storage for rendered bitmaps with their metadata, by bitmap ID
+    bitmaps: LruCache<Bitmap>,
+    /// ID of the most recently rendered bitmap of each stored SVG
+    latest: FxHashMap<String, String>,
+    /// Renders the bitmaps
+    renderer: Renderer,
+}
+
+impl Default for SvgManager {
+    fn default() -> Self {
+        Self::with_limits(CacheLimits::default())
+    }
 }
 
 impl SvgManager {
-    /// Create a new SVG manager
+    /// Create a new SVG manager with the default cache limits
     pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a new SVG manager with the given cache limits
+    pub fn with_limits(limits: CacheLimits) -> Self {
         SvgManager {
-            svgs: FxHashMap::default(),
-            bitmaps: FxHashMap::default(),
+            svgs: LruCache::new(limits.svgs),
+            bitmaps: LruCache::new(limits.bitmaps),
+            latest: FxHashMap::default(),
+            renderer: Renderer::default(),
+        }
+    }
+
+    /// Look up and store bitmaps in a cache on disk as well as in memory
+    pub fn set_disk_cache(&mut self, disk_cache: Option<DiskCache>) {
+        self.renderer.disk_cache = disk_cache;
+    }
+
+    /// The renderer used for new bitmaps
+    pub fn renderer(&self) -> &Renderer {
+        &self.renderer
+    }
+
+    /// Load the fonts of `config` for `<text>` elements, dropping bitmaps rendered with other fonts
+    pub fn set_fonts(&mut self, config: FontConfig) -> Result<()> {
+        let mut renderer = self.renderer.clone();
+        renderer.set_fonts(config)?;
+        self.set_renderer(renderer);
+        Ok(())
+    }
+
+    /// Change the cache limits, evicting entries as needed
+    pub fn set_limits(&mut self, limits: CacheLimits) {
+        let evicted = self.svgs.set_limit(limits.svgs);
+        self.forget_svgs(evicted);
+        let evicted = self.bitmaps.set_limit(limits.bitmaps);
+        self.forget_bitmaps(evicted);
+    }
+
+    /// Number of entries, bytes and evictions of each cache
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            svgs: self.svgs.usage(),
+            bitmaps: self.bitmaps.usage(),
+        }
+    }
+
+    fn forget_svgs(&mut self, ids: Vec<String>) {
+        for id in ids {
+            self.latest.remove(&id);
+        }
+    }
+
+    fn forget_bitmaps(&mut self, bitmap_ids: Vec<String>) {
+        for bitmap_id in bitmap_ids {
+            // The SVG ID is everything before the last `@`, see `bitmap_id`
+            let Some((id, _)) = bitmap_id.rsplit_once('@') else {
+                continue;
+            };
+            if self.latest.get(id) == Some(&bitmap_id) {
+                self.latest.remove(id);
+            }
         }
     }
 
@@ -86,10 +250,92 @@ impl SvgManager {
         format!("{:x}", hasher.finalize())[..16].to_string()
     }
 
+    /// ID of the bitmap rendered from SVG `id` at the requested size with `options`
+    ///
+    /// Bitmap IDs have the form `<svg id>@<width>x<height>`, with `_` for a
+    /// dimension that follows the aspect ratio, e.g. `9f86d081884c7d65@200x_`.
+    /// Options other than the defaults append `-` and the [`SvgManager::generate_id`]
+    /// hash of their JSON form. The size is the requested one rather than the
+    /// resolved one, so that finding a cached bitmap does not need to parse the SVG.
+    pub fn bitmap_id(
+        id: &str,
+        width: Option<u32>,
+        height: Option<u32>,
+        options: &RenderOptions,
+    ) -> String {
+        let dimension = |d: Option<u32>| d.map_or_else(|| "_".to_string(), |d| d.to_string());
+        let mut bitmap_id = format!("{}@{}x{}", id, dimension(width), dimension(height));
+        if !options.is_default() {
+            let options = serde_json::to_string(options).unwrap_or_default();
+            bitmap_id.push('-');
+            bitmap_id.push_str(&Self::generate_id(&options));
+        }
+        bitmap_id
+    }
+
+    /// A small red SVG showing `message`, to stand in for content that failed to paint
+    pub fn error_svg(message: &str) -> String {
+        const MAX_CHARS: usize = 120;
+        const HEIGHT: f32 = 18.0;
+
+        let mut text: String = message
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .chars()
+            .take(MAX_CHARS)
+            .collect();
+        if message.chars().count() > MAX_CHARS {
+            text.push('…');
+        }
+        let text = text
+            .replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;");
+
+        // The text is drawn as paths with the bundled font, so it shows without any fonts
+        let source = format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="1" height="{HEIGHT}"><text x="4" y="13" font-family="DejaVu Sans Mono" font-size="12">{text}</text></svg>"#
+        );
+        let options = usvg::Options {
+            fontdb: fonts::bundled_database(),
+            ..usvg::Options::default()
+        };
+        let color = tiny_skia::Color::from_rgba8(0xcc, 0, 0, 255);
+        let mut glyphs = String::new();
+        let mut right = HEIGHT;
+        if let Ok(tree) = usvg::Tree::from_str(&source, &options) {
+            for node in tree.root().children() {
+                let usvg::Node::Text(text) = node else {
+                    continue;
+                };
+                right = right.max(text.abs_bounding_box().right());
+                for node in text.flattened().children() {
+                    let usvg::Node::Path(path) = node else {
+                        continue;
+                    };
+                    if let Some(data) = path.data().clone().transform(path.abs_transform()) {
+                        normalize::push_path(&mut glyphs, &data, "fill", color);
+                        glyphs.push_str("/>");
+                    }
+                }
+            }
+        }
+        let width = right.ceil() + 4.0;
+
+        format!(
+            r##"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{HEIGHT}" viewBox="0 0 {width} {HEIGHT}" style="vertical-align: -5px;"><title>{text}</title><rect x="0.5" y="0.5" width="{rect}" height="17" rx="2" fill="#fee" stroke="#c00"/>{glyphs}</svg>"##,
+            rect = width - 1.0,
+        )
+    }
+
     /// Store an SVG and return its ID
     pub fn store_svg(&mut self, svg_data: &str, custom_id: Option<String>) -> String {
         let id = custom_id.unwrap_or_else(|| Self::generate_id(svg_data));
-        self.svgs.insert(id.clone(), svg_data.to_string());
+        let evicted = self
+            .svgs
+            .insert(id.clone(), svg_data.to_string(), svg_data.len());
+        self.forget_svgs(evicted);
         id
     }
 
@@ -104,74 +350,70 @@ impl SvgManager {
         id: &str,
         width: Option<u32>,
         height: Option<u32>,
+    ) -> Result<(u32, u32)> {
+        self.render_svg_with_options(id, width, height, &RenderOptions::default())
+    }
+
+    /// Render an SVG to a bitmap with specified dimensions and colors
+    ///
+    /// The bitmap is stored under [`SvgManager::bitmap_id`], next to any
+    /// other variants of the same SVG.
+    pub fn render_svg_with_options(
+        &mut self,
+        id: &str,
+        width: Option<u32>,
+        height: Option<u32>,
+        options: &RenderOptions,
     ) -> Result<(u32, u32)> {
         let svg_data = self
             .get_svg(id)
             .ok_or_else(|| anyhow::anyhow!("SVG not found"))?;
+        let bitmap = self.renderer.render(svg_data, width, height, options)?;
+        let size = (bitmap.width, bitmap.height);
+        self.store_bitmap(id, Self::bitmap_id(id, width, height, options), bitmap);
+        Ok(size)
+    }
 
-        // Parse the SVG
-        let opt = usvg::Options::default();
-        // log::trace!("{svg_data}");
-        let tree = match usvg::Tree::from_str(svg_data, &opt) {
-            Ok(tree) => {
-                log::trace!("SVG validation successful for id: {}", id);
-                tree
-            }
-            Err(e) => {
-                return Err(anyhow::anyhow!("Failed to parse SVG: {}", e));
-            }
-        };
-
-        // Get original size
-        let orig_size = tree.size();
-
-        // Calculate target size
-        let (target_width, target_height) = match (width, height) {
-            (Some(w), Some(h)) => (w, h),
-            (Some(w), None) => {
-                let aspect = orig_size.height() / orig_size.width();
-                (w, (w as f32 * aspect) as u32)
-            }
-            (None, Some(h)) => {
-                let aspect = orig_size.width() / orig_size.height();
-                ((h as f32 * aspect) as u32, h)
-            }
-            (None, None) => (orig_size.width() as u32, orig_size.height() as u32),
-        };
-        log::trace!(
-            "calculated ({target_width}, {target_height}) from ({width:?}, {height:?}) with {orig_size:?}"
-        );
-
-        // Create a pixmap with the target size
-        let mut pixmap = tiny_skia::Pixmap::new(target_width, target_height)
-            .ok_or_else(|| anyhow::anyhow!("Failed to create pixmap"))?;
+    /// Store a bitmap rendered elsewhere from `svg_data`, returning whether the SVG was already stored
+    fn insert_rendered(
+        &mut self,
+        id: &str,
+        svg_data: &str,
+        bitmap_id: String,
+        bitmap: Bitmap,
+    ) -> bool {
+        let cached = self.get_svg(id).is_some();
+        if !cached {
+            self.store_svg(svg_data, Some(id.to_string()));
+        }
+        self.store_bitmap(id, bitmap_id, bitmap);
+        cached
+    }
 
-        let transform = usvg::Transform::from_scale(
-            target_width as f32 / orig_size.width(),
-            target_height as f32 / orig_size.height(),
-        );
-        // Render the SVG
-        resvg::render(&tree, transform, &mut pixmap.as_mut());
-
-        // Convert to PNG
-        let png_data = pixmap.encode_png()?;
-
-        // Store the bitmap with its metadata
-        self.bitmaps.insert(
-            id.to_string(),
-            Bitmap {
-                data: png_data,
-                width: target_width,
-                height: target_height,
-            },
-        );
+    /// Replace the renderer, dropping bitmaps rendered by the previous one
+    fn set_renderer(&mut self, renderer: Renderer) {
+        self.renderer = renderer;
+        self.bitmaps.clear();
+        self.latest.clear();
+    }
 
-        Ok((target_width, target_height))
+    fn store_bitmap(&mut self, id: &str, bitmap_id: String, bitmap: Bitmap) {
+        let bytes = bitmap_id.len() + bitmap.data.len();
+        let evicted = self.bitmaps.insert(bitmap_id.clone(), bitmap, bytes);
+        self.latest.insert(id.to_string(), bitmap_id);
+        self.forget_bitmaps(evicted);
     }
 
     /// Get a rendered bitmap by ID
+    ///
+    /// Given the ID of an SVG rather than a bitmap, returns the bitmap most
+    /// recently rendered from it.
     pub fn get_bitmap(&self, id: &str) -> Option<&Bitmap> {
-        self.bitmaps.get(id)
+        self.bitmaps.get(id).or_else(|| {
+            self.latest
+                .get(id)
+                .and_then(|bitmap_id| self.bitmaps.get(bitmap_id))
+        })
     }
 
     /// Process a render request
@@ -181,9 +423,11 @@ impl SvgManager {
             .id
             .unwrap_or_else(|| Self::generate_id(&request.svg_data));
 
-        if let Some(bitmap) = self.get_bitmap(&id) {
+        let bitmap_id = Self::bitmap_id(&id, request.width, request.height, &request.options);
+
+        if let Some(bitmap) = self.get_bitmap(&bitmap_id) {
             return Ok(RenderResponse {
-                id,
+                id: bitmap_id,
                 cached: true,
                 bitmap: bitmap.clone(),
             });
@@ -197,21 +441,17 @@ impl SvgManager {
         }
 
         // Render the SVG
-        let (width, height) = self.render_svg(&id, request.width, request.height)?;
+        self.render_svg_with_options(&id, request.width, request.height, &request.options)?;
 
         // Get the bitmap
         let bitmap = self
-            .get_bitmap(&id)
+            .get_bitmap(&bitmap_id)
             .ok_or_else(|| anyhow::anyhow!("Bitmap not found after rendering"))?;
 
         Ok(RenderResponse {
-            id,
+            id: bitmap_id,
             cached,
-            bitmap: Bitmap {
-                data: bitmap.data.clone(),
-                width: bitmap.width,
-                height: bitmap.height,
-            },
+            bitmap: bitmap.clone(),
         })
     }
 
@@ -226,28 +466,451 @@ impl SvgManager {
 
         Ok(GetBitmapResponse {
             id: request.id,
-            bitmap: Bitmap {
-                data: bitmap.data.clone(),
-                width: bitmap.width,
-                height: bitmap.height,
-            },
+            bitmap: bitmap.clone(),
+            paint_error: None,
+        })
+    }
+}
+
+/// Turns SVG data into bitmaps, independently of any [`SvgManager`] state
+///
+/// Cloning is cheap, so that rendering can move to another thread while
+/// the manager stays available.
+#[derive(Debug, Clone, Default)]
+pub struct Renderer {
+    /// Bitmaps kept across restarts, keyed by SVG content rather than ID
+    disk_cache: Option<DiskCache>,
+    /// Fonts for `<text>` elements, their config being part of the disk cache key
+    fonts: LazyFonts,
+}
+
+impl Renderer {
+    /// Use the fonts of `config` for subsequent renders, loading them once an SVG has text
+    pub fn set_fonts(&mut self, config: FontConfig) -> Result<()> {
+        self.fonts = LazyFonts::new(config)?;
+        Ok(())
+    }
+
+    /// Number of font faces available, loading the fonts if they are not yet
+    pub fn font_faces(&self) -> usize {
+        self.fonts.get().len()
+    }
+
+    /// Render SVG data, going through the disk cache if one is set
+    pub fn render(
+        &self,
+        svg_data: &str,
+        width: Option<u32>,
+        height: Option<u32>,
+        options: &RenderOptions,
+    ) -> Result<Bitmap> {
+        // Bitmaps on disk are addressed by the content, as custom IDs may be reused
+        let mut disk_key =
+            SvgManager::bitmap_id(&SvgManager::generate_id(svg_data), width, height, options);
+        if !self.fonts.config().is_default() {
+            let fonts = serde_json::to_string(self.fonts.config()).unwrap_or_default();
+            disk_key.push_str(&format!("-fonts-{}", SvgManager::generate_id(&fonts)));
+        }
+        let on_disk = self.disk_cache.as_ref().and_then(|disk_cache| {
+            let data = disk_cache.get(CacheKind::Bitmap, &disk_key)?;
+            decode_bitmap(&data)
+        });
+        if let Some(bitmap) = on_disk {
+            log::trace!("loaded bitmap {} from the disk cache", disk_key);
+            return Ok(bitmap);
+        }
+
+        let bitmap = self.rasterize(svg_data, width, height, options)?;
+        if let Some(disk_cache) = &self.disk_cache {
+            disk_cache.put(CacheKind::Bitmap, &disk_key, &encode_bitmap(&bitmap));
+        }
+        Ok(bitmap)
+    }
+
+    /// Parse SVG data and render it in the requested format
+    fn rasterize(
+        &self,
+        svg_data: &str,
+        width: Option<u32>,
+        height: Option<u32>,
+        options: &RenderOptions,
+    ) -> Result<Bitmap> {
+        let foreground = options.foreground.as_deref().map(parse_color).transpose()?;
+        let frame = Frame::from_options(options)?;
+        let scale = options.scale.unwrap_or(1.0);
+        if !(scale.is_finite() && scale > 0.0) {
+            anyhow::bail!("Invalid scale {}, must be a positive number", scale);
+        }
+        let format = options.format.unwrap_or_default();
+        format.check_quality(options.quality)?;
+        // Vector formats do not depend on the device, so their size and metrics stay logical
+        let scale = if format.is_vector() { 1.0 } else { scale };
+
+        // Parse the SVG, resolving `currentColor` to the foreground
+        let opt = usvg::Options {
+            style_sheet: foreground.map(|c| {
+                format!(
+                    "svg {{ color: rgba({}, {}, {}, {}); }}",
+                    c.red,
+                    c.green,
+                    c.blue,
+                    c.alpha as f32 / 255.0
+                )
+            }),
+            fontdb: self.fonts.for_svg(svg_data),
+            ..Default::default()
+        };
+        // log::trace!("{svg_data}");
+        let tree = match usvg::Tree::from_str(svg_data, &opt) {
+            Ok(tree) => {
+                log::trace!("SVG validation successful");
+                tree
+            }
+            Err(e) => {
+                return Err(anyhow::anyhow!("Failed to parse SVG: {}", e));
+            }
+        };
+
+        // Calculate the logical size, then the size in device pixels
+        let orig_size = tree.size();
+        // A trimmed SVG is sized as if the drawn area were all of it
+        let trimmed = match options.trim {
+            Some(trim) => {
+                // Look for drawn pixels at about the resolution of the untrimmed SVG at the requested size
+                let untrimmed = options
+                    .fit
+                    .unwrap_or_default()
+                    .layout(orig_size, width, height);
+                let probe_scale = scale * untrimmed.transform.sx.max(untrimmed.transform.sy);
+                trim.content_rect(&tree, probe_scale)?
+            }
+            None => None,
+        };
+        // Rounded up to whole pixels, so that an area thinner than a pixel still gets one
+        let natural = trimmed
+            .and_then(|rect| {
+                usvg::Size::from_wh(rect.width().ceil().max(1.0), rect.height().ceil().max(1.0))
+            })
+            .unwrap_or(orig_size);
+        let mut layout = options
+            .fit
+            .unwrap_or_default()
+            .layout(natural, width, height);
+        if let Some(rect) = trimmed {
+            layout.transform = layout.transform.pre_translate(-rect.x(), -rect.y());
+            layout.clip = true;
+        }
+        // The frame adds its margin and padding around the requested size
+        let (outer_width, outer_height) = frame.size(layout.width as f32, layout.height as f32);
+        let (logical_width, logical_height) =
+            (outer_width.round() as u32, outer_height.round() as u32);
+        let target_width = (logical_width as f32 * scale).round() as u32;
+        let target_height = (logical_height as f32 * scale).round() as u32;
+        log::trace!(
+            "calculated ({target_width}, {target_height}) from ({width:?}, {height:?}) at {scale}x with {orig_size:?}"
+        );
+
+        let data = match format {
+            ImageFormat::Pdf => pdf::tree_to_pdf(&tree, &layout, &frame)?,
+            ImageFormat::NormalizedSvg => {
+                normalize::normalize_svg(&tree, &layout, &frame, options.text_to_paths)?
+                    .into_bytes()
+            }
+            _ => self.rasterize_tree(
+                &tree,
+                (target_width, target_height),
+                &layout,
+                &frame,
+                scale,
+                format,
+                options.quality,
+            )?,
+        };
+
+        // Baseline metrics follow the vertical scale of the SVG, then its place in the image
+        let metrics = SvgMetrics::from_svg(svg_data).map(|m| {
+            let content_width = orig_size.width() * layout.transform.sx * scale;
+            let content_height = orig_size.height() * layout.transform.sy * scale;
+            m.scaled_to(content_width, content_height).placed(
+                (frame.offset() + layout.transform.ty) * scale,
+                target_width as f32,
+                target_height as f32,
+            )
+        });
+
+        Ok(Bitmap {
+            data,
+            format,
+            stride: format.stride(target_width),
+            width: target_width,
+            height: target_height,
+            logical_width,
+            logical_height,
+            metrics,
+            trim_offset: trimmed.map(|rect| TrimOffset {
+                x: rect.x(),
+                y: rect.y(),
+            }),
         })
     }
+
+    /// Rasterize a parsed SVG into its frame and encode the pixels
+    ///
+    /// The image is `target` device pixels, with the SVG placed by the
+    /// layout inside the margin and padding.
+    #[allow(clippy::too_many_arguments)]
+    fn rasterize_tree(
+        &self,
+        tree: &usvg::Tree,
+        target: (u32, u32),
+        layout: &Layout,
+        frame: &Frame,
+        scale: f32,
+        format: ImageFormat,
+        quality: Option<u8>,
+    ) -> Result<Vec<u8>> {
+        // Create a pixmap with the target size
+        let mut pixmap = tiny_skia::Pixmap::new(target.0, target.1)
+            .ok_or_else(|| anyhow::anyhow!("Failed to create pixmap"))?;
+        let (width, height) = (target.0 as f32 / scale, target.1 as f32 / scale);
+        let device = usvg::Transform::from_scale(scale, scale);
+        let background = frame
+            .background
+            .zip(frame.background_outline(width, height));
+        if let Some((c, outline)) = background {
+            let mut paint = tiny_skia::Paint::default();
+            paint.set_color(c);
+            pixmap.fill_path(&outline, &paint, tiny_skia::FillRule::Winding, device, None);
+        }
+
+        let transform = device
+            .pre_translate(frame.offset(), frame.offset())
+            .pre_concat(layout.transform);
+        // Render the SVG, on its own when the overflow has to be cut off
+        if layout.clip {
+            let mut content = tiny_skia::Pixmap::new(target.0, target.1)
+                .ok_or_else(|| anyhow::anyhow!("Failed to create pixmap"))?;
+            resvg::render(tree, transform, &mut content.as_mut());
+            let mut clip = tiny_skia::Mask::new(target.0, target.1)
+                .ok_or_else(|| anyhow::anyhow!("Failed to create mask"))?;
+            if let Some(rect) = tiny_skia::Rect::from_xywh(
+                frame.offset(),
+                frame.offset(),
+                layout.width as f32,
+                layout.height as f32,
+            ) {
+                let rect = tiny_skia::PathBuilder::from_rect(rect);
+                clip.fill_path(&rect, tiny_skia::FillRule::Winding, true, device);
+            }
+            pixmap.draw_pixmap(
+                0,
+                0,
+                content.as_ref(),
+                &tiny_skia::PixmapPaint::default(),
+                usvg::Transform::identity(),
+                Some(&clip),
+            );
+        } else {
+            resvg::render(tree, transform, &mut pixmap.as_mut());
+        }
+
+        let border = frame.border.zip(frame.border_outline(width, height));
+        if let Some(((c, width), outline)) = border {
+            let mut paint = tiny_skia::Paint::default();
+            paint.set_color(c);
+            let stroke = tiny_skia::Stroke {
+                width,
+                ..Default::default()
+            };
+            pixmap.stroke_path(&outline, &paint, &stroke, device, None);
+        }
+
+        format.encode(&pixmap, quality)
+    }
+}
+
+/// Size, format and metrics stored in front of the data of a bitmap on disk
+#[derive(Serialize, Deserialize)]
+struct BitmapHeader {
+    /// Missing from entries written before other formats existed, which are PNG
+    #[serde(default)]
+    format: ImageFormat,
+    width: u32,
+    height: u32,
+    logical_width: u32,
+    logical_height: u32,
+    metrics: Option<SvgMetrics>,
+    #[serde(default)]
+    trim_offset: Option<TrimOffset>,
+}
+
+/// Serialize a bitmap for the disk cache as a JSON header line followed by the data
+fn encode_bitmap(bitmap: &Bitmap) -> Vec<u8> {
+    let header = BitmapHeader {
+        format: bitmap.format,
+        width: bitmap.width,
+        height: bitmap.height,
+        logical_width: bitmap.logical_width,
+        logical_height: bitmap.logical_height,
+        metrics: bitmap.metrics,
+        trim_offset: bitmap.trim_offset,
+    };
+    let mut data = serde_json::to_vec(&header).unwrap_or_default();
+    data.push(b'\n');
+    data.extend_from_slice(&bitmap.data);
+    data
+}
+
+fn decode_bitmap(data: &[u8]) -> Option<Bitmap> {
+    let newline = data.iter().position(|&b| b == b'\n')?;
+    let header: BitmapHeader = serde_json::from_slice(&data[..newline]).ok()?;
+    Some(Bitmap {
+        data: data[newline + 1..].to_vec(),
+        format: header.format,
+        stride: header.format.stride(header.width),
+        width: header.width,
+        height: header.height,
+        logical_width: header.logical_width,
+        logical_height: header.logical_height,
+        metrics: header.metrics,
+        trim_offset: header.trim_offset,
+    })
 }
 
 /// Thread-safe wrapper around SvgManager
-#[derive(Clone)]
-pub struct SharedSvgManager(Arc<RwLock<SvgManager>>);
+#[derive(Debug, Clone, Default)]
+pub struct SharedSvgManager {
+    manager: Arc<RwLock<SvgManager>>,
+    /// Renders in progress, by bitmap ID
+    inflight: SingleFlight<RenderResponse>,
+}
 
 impl SharedSvgManager {
     /// Create a new shared SVG manager
     pub fn new() -> Self {
-        SharedSvgManager(Arc::new(RwLock::new(SvgManager::new())))
+        Self::default()
     }
 
-    /// Process a render request
+    /// Create a new shared SVG manager with the given cache limits
+    pub fn with_limits(limits: CacheLimits) -> Self {
+        SharedSvgManager {
+            manager: Arc::new(RwLock::new(SvgManager::with_limits(limits))),
+            inflight: SingleFlight::new(),
+        }
+    }
+
+    /// Number of entries, bytes and evictions of each cache
+    pub fn stats(&self) -> CacheStats {
+        self.manager.read().unwrap().stats()
+    }
+
+    /// Look up and store bitmaps in a cache on disk as well as in memory
+    pub fn set_disk_cache(&self, disk_cache: Option<DiskCache>) {
+        self.manager.write().unwrap().set_disk_cache(disk_cache)
+    }
+
+    /// Load the fonts of `config` for `<text>` elements, returning the number of font faces
+    pub fn set_fonts(&self, config: FontConfig) -> Result<usize> {
+        // Loading fonts can take a while, so it happens before taking the lock
+        let mut renderer = self.manager.read().unwrap().renderer().clone();
+        renderer.set_fonts(config)?;
+        let faces = renderer.font_faces();
+        self.manager.write().unwrap().set_renderer(renderer);
+        Ok(faces)
+    }
+
+    /// Process a render request on the calling thread
+    ///
+    /// The SVG is rendered without holding the lock, which is only taken
+    /// to look up and store the bitmap, so other requests are not blocked.
     pub fn process_render_request(&self, request: RenderRequest) -> Result<RenderResponse> {
-        self.0.write().unwrap().process_render_request(request)
+        let (id, bitmap_id) = Self::request_ids(&request);
+        let renderer = match self.lookup(&bitmap_id) {
+            ControlFlow::Break(response) => return Ok(response),
+            ControlFlow::Continue(renderer) => renderer,
+        };
+
+        let bitmap = renderer.render(
+            &request.svg_data,
+            request.width,
+            request.height,
+            &request.options,
+        )?;
+        Ok(self.insert(&id, &request.svg_data, bitmap_id, bitmap))
+    }
+
+    /// Process a render request, sharing the work with concurrent requests for the same bitmap
+    ///
+    /// Unlike [`SharedSvgManager::process_render_request`], the SVG is
+    /// rendered on a blocking thread, so that the async runtime keeps running.
+    pub async fn render(&self, request: RenderRequest) -> Result<RenderResponse> {
+        let (id, bitmap_id) = Self::request_ids(&request);
+        let renderer = match self.lookup(&bitmap_id) {
+            ControlFlow::Break(response) => return Ok(response),
+            ControlFlow::Continue(renderer) => renderer,
+        };
+
+        self.inflight
+            .run(&bitmap_id.clone(), || async move {
+                let RenderRequest {
+                    svg_data,
+                    width,
+                    height,
+                    options,
+                    ..
+                } = request;
+                let (svg_data, bitmap) = tokio::task::spawn_blocking(move || {
+                    let bitmap = renderer.render(&svg_data, width, height, &options);
+                    (svg_data, bitmap)
+                })
+                .await?;
+                Ok(self.insert(&id, &svg_data, bitmap_id, bitmap?))
+            })
+            .await
+    }
+
+    /// IDs of the SVG and the bitmap of a render request
+    fn request_ids(request: &RenderRequest) -> (String, String) {
+        let id = request
+            .id
+            .clone()
+            .unwrap_or_else(|| SvgManager::generate_id(&request.svg_data));
+        let bitmap_id = SvgManager::bitmap_id(&id, request.width, request.height, &request.options);
+        (id, bitmap_id)
+    }
+
+    /// The response for a cached bitmap, or else a renderer to render it with outside the lock
+    fn lookup(&self, bitmap_id: &str) -> ControlFlow<RenderResponse, Renderer> {
+        let manager = self.manager.read().unwrap();
+        match manager.get_bitmap(bitmap_id) {
+            Some(bitmap) => ControlFlow::Break(RenderResponse {
+                id: bitmap_id.to_string(),
+                cached: true,
+                bitmap: bitmap.clone(),
+            }),
+            None => ControlFlow::Continue(manager.renderer().clone()),
+        }
+    }
+
+    /// Store a bitmap rendered outside the lock, with its SVG
+    fn insert(
+        &self,
+        id: &str,
+        svg_data: &str,
+        bitmap_id: String,
+        bitmap: Bitmap,
+    ) -> RenderResponse {
+        let cached = self.manager.write().unwrap().insert_rendered(
+            id,
+            svg_data,
+            bitmap_id.clone(),
+            bitmap.clone(),
+        );
+        RenderResponse {
+            id: bitmap_id,
+            cached,
+            bitmap,
+        }
     }
 
     /// Process a get bitmap request
@@ -255,11 +918,9 @@ impl SharedSvgManager {
         &self,
         request: GetBitmapRequest,
     ) -> Result<GetBitmapResponse> {
-        self.0.read().unwrap().process_get_bitmap_request(request)
-    }
-
-    /// Clone the shared manager
-    pub fn clone(&self) -> Self {
-        SharedSvgManager(Arc::clone(&self.0))
+        self.manager
+            .read()
+            .unwrap()
+            .process_get_bitmap_request(request)
     }
 }
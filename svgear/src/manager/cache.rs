@@ -0,0 +1,191 @@
+use fxhash::FxHashMap;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Limits on one of the caches of an [`SvgManager`](super::SvgManager)
+///
+/// Once either limit is exceeded, the least recently used entries are evicted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheLimit {
+    /// Maximum total size of the entries in bytes, unlimited if unset
+    pub max_bytes: Option<usize>,
+    /// Maximum number of entries, unlimited if unset
+    pub max_entries: Option<usize>,
+}
+
+impl CacheLimit {
+    /// No limit at all
+    pub const UNLIMITED: CacheLimit = CacheLimit {
+        max_bytes: None,
+        max_entries: None,
+    };
+
+    fn exceeded(&self, entries: usize, bytes: usize) -> bool {
+        self.max_entries.is_some_and(|max| entries > max)
+            || self.max_bytes.is_some_and(|max| bytes > max)
+    }
+}
+
+/// Limits on the SVG sources and the bitmaps kept by an [`SvgManager`](super::SvgManager)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheLimits {
+    pub svgs: CacheLimit,
+    pub bitmaps: CacheLimit,
+}
+
+impl Default for CacheLimits {
+    /// 16 MiB of SVG sources and 64 MiB of bitmaps
+    fn default() -> Self {
+        CacheLimits {
+            svgs: CacheLimit {
+                max_bytes: Some(16 << 20),
+                max_entries: None,
+            },
+            bitmaps: CacheLimit {
+                max_bytes: Some(64 << 20),
+                max_entries: None,
+            },
+        }
+    }
+}
+
+/// Current contents of one cache
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheUsage {
+    /// Number of entries
+    pub entries: usize,
+    /// Total size of the entries in bytes
+    pub bytes: usize,
+    /// Entries evicted to stay within the limit so far
+    pub evictions: u64,
+}
+
+/// Snapshot of the caches of an [`SvgManager`](super::SvgManager)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheStats {
+    pub svgs: CacheUsage,
+    pub bitmaps: CacheUsage,
+}
+
+/// A map that evicts its least recently used entries to stay within a [`CacheLimit`]
+///
+/// Reads only need `&self`, so that they can happen under a shared lock.
+/// Eviction scans for the oldest entry, which is cheap next to rendering.
+#[derive(Debug)]
+pub(crate) struct LruCache<V> {
+    entries: FxHashMap<String, Entry<V>>,
+    limit: CacheLimit,
+    bytes: usize,
+    evictions: u64,
+    clock: AtomicU64,
+}
+
+#[derive(Debug)]
+struct Entry<V> {
+    value: V,
+    bytes: usize,
+    last_used: AtomicU64,
+}
+
+impl<V> LruCache<V> {
+    pub fn new(limit: CacheLimit) -> Self {
+        LruCache {
+            entries: FxHashMap::default(),
+            limit,
+            bytes: 0,
+            evictions: 0,
+            clock: AtomicU64::new(0),
+        }
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Get an entry and mark it as recently used
+    pub fn get(&self, key: &str) -> Option<&V> {
+        let entry = self.entries.get(key)?;
+        entry.last_used.store(self.tick(), Ordering::Relaxed);
+        Some(&entry.value)
+    }
+
+    /// Insert an entry weighing `bytes`, returning the keys evicted to make room
+    ///
+    /// The new entry itself is never evicted, even if it is over the limit on its own.
+    pub fn insert(&mut self, key: String, value: V, bytes: usize) -> Vec<String> {
+        let entry = Entry {
+            value,
+            bytes,
+            last_used: AtomicU64::new(self.tick()),
+        };
+        self.bytes += bytes;
+        if let Some(old) = self.entries.insert(key.clone(), entry) {
+            self.bytes -= old.bytes;
+        }
+        self.evict(Some(&key))
+    }
+
+    /// Remove every entry, without counting them as evictions
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.bytes = 0;
+    }
+
+    /// Change the limit, evicting entries as needed
+    pub fn set_limit(&mut self, limit: CacheLimit) -> Vec<String> {
+        self.limit = limit;
+        self.evict(None)
+    }
+
+    fn evict(&mut self, keep: Option<&str>) -> Vec<String> {
+        let mut evicted = Vec::new();
+        while self.limit.exceeded(self.entries.len(), self.bytes) {
+            let oldest = self
+                .entries
+                .iter()
+                .filter(|(key, _)| Some(key.as_str()) != keep)
+                .min_by_key(|(_, entry)| entry.last_used.load(Ordering::Relaxed))
+                .map(|(key, _)| key.clone());
+            let Some(key) = oldest else {
+                break;
+            };
+            if let Some(entry) = self.entries.remove(&key) {
+                self.bytes -= entry.bytes;
+            }
+            self.evictions += 1;
+            evicted.push(key);
+        }
+        evicted
+    }
+
+    pub fn usage(&self) -> CacheUsage {
+        CacheUsage {
+            entries: self.entries.len(),
+            bytes: self.bytes,
+            evictions: self.evictions,
+        }
+    }
+}
+
+impl<V: Clone> Clone for LruCache<V> {
+    fn clone(&self) -> Self {
+        LruCache {
+            entries: self
+                .entries
+                .iter()
+                .map(|(key, entry)| {
+                    let entry = Entry {
+                        value: entry.value.clone(),
+                        bytes: entry.bytes,
+                        last_used: AtomicU64::new(entry.last_used.load(Ordering::Relaxed)),
+                    };
+                    (key.clone(), entry)
+                })
+                .collect(),
+            limit: self.limit,
+            bytes: self.bytes,
+            evictions: self.evictions,
+            clock: AtomicU64::new(self.clock.load(Ordering::Relaxed)),
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use serde::{Deserialize, Serialize};
+
+/// Pixels per `ex`, matching usvg which resolves `1ex` to half of its default 12px font size
+pub const EX_TO_PX: f32 = 6.0;
+
+/// Size and baseline position of a painted SVG, in pixels
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SvgMetrics {
+    /// Width of the image
+    pub width: f32,
+    /// Height of the image
+    pub height: f32,
+    /// Distance from the top edge down to the baseline
+    pub ascent: f32,
+    /// Distance from the baseline down to the bottom edge
+    pub depth: f32,
+}
+
+impl SvgMetrics {
+    /// Parse metrics from the `width`, `height` and `vertical-align` of the root `<svg>` element
+    ///
+    /// MathJax emits these in `ex`, e.g.
+    /// `<svg width="2.009ex" height="2.343ex" style="vertical-align: -0.671ex;" ...>`.
+    /// Returns `None` if the root element has no usable size.
+    pub fn from_svg(svg: &str) -> Option<Self> {
+        let start = svg.find("<svg")?;
+        let tag = &svg[start..];
+        let tag = &tag[..tag.find('>')?];
+
+        let width = parse_length(attribute(tag, "width")?)?;
+        let height = parse_length(attribute(tag, "height")?)?;
+        let vertical_align = attribute(tag, "style")
+            .and_then(|style| style_property(style, "vertical-align"))
+            .and_then(parse_length)
+            .unwrap_or(0.0);
+        let depth = (-vertical_align).clamp(0.0, height);
+
+        Some(SvgMetrics {
+            width,
+            height,
+            ascent: height - depth,
+            depth,
+        })
+    }
+
+    /// Scale the metrics so that they match an image of `width` by `height` pixels
+    ///
+    /// The axes scale separately, as they do when an SVG is stretched.
+    pub fn scaled_to(&self, width: f32, height: f32) -> Self {
+        let factor = |from: f32, to: f32| if from > 0.0 { to / from } else { 1.0 };
+        let (fx, fy) = (factor(self.width, width), factor(self.height, height));
+        SvgMetrics {
+            width: self.width * fx,
+            height: self.height * fy,
+            ascent: self.ascent * fy,
+            depth: self.depth * fy,
+        }
+    }
+
+    /// Metrics of an image of `width` by `height` with the SVG placed `top` pixels down
+    pub fn placed(&self, top: f32, width: f32, height: f32) -> Self {
+        let ascent = (top + self.ascent).clamp(0.0, height);
+        SvgMetrics {
+            width,
+            height,
+            ascent,
+            depth: height - ascent,
+        }
+    }
+
+    /// The ascent as a percentage of the height, as expected by the Emacs `:ascent` image property
+    pub fn ascent_percent(&self) -> u8 {
+        if self.height <= 0.0 {
+            return 100;
+        }
+        (self.ascent / self.height * 100.0)
+            .round()
+            .clamp(0.0, 100.0) as u8
+    }
+}
+
+/// Find the value of an attribute inside a start tag
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let mut offset = 0;
+    while let Some(pos) = tag[offset..].find(name) {
+        let pos = offset + pos;
+        offset = pos + name.len();
+
+        let preceded_by_space = tag[..pos].ends_with(|c: char| c.is_ascii_whitespace());
+        let rest = tag[offset..].trim_start();
+        if !preceded_by_space || !rest.starts_with('=') {
+            continue;
+        }
+
+        let value = rest[1..].trim_start();
+        let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+        let value = &value[1..];
+        return value.find(quote).map(|end| &value[..end]);
+    }
+    None
+}
+
+/// Find the value of a property inside an inline `style` attribute
+fn style_property<'a>(style: &'a str, name: &str) -> Option<&'a str> {
+    style.split(';').find_map(|decl| {
+        let (key, value) = decl.split_once(':')?;
+        (key.trim() == name).then(|| value.trim())
+    })
+}
+
+/// Parse a length in `ex` or `px` (the default) into pixels
+fn parse_length(value: &str) -> Option<f32> {
+    let value = value.trim();
+    if let Some(ex) = value.strip_suffix("ex") {
+        ex.trim().parse::<f32>().ok().map(|v| v * EX_TO_PX)
+    } else {
+        value
+            .strip_suffix("px")
+            .unwrap_or(value)
+            .trim()
+            .parse()
+            .ok()
+    }
+}
@@ -0,0 +1,167 @@
+use anyhow::{Context, Result};
+use resvg::tiny_skia;
+use resvg::usvg::{self, tiny_skia_path::PathSegment, Transform};
+
+use crate::fit::Layout;
+use crate::frame::Frame;
+
+/// Re-serialize a parsed SVG as a minified, self-contained SVG placed by the layout
+///
+/// usvg has already resolved `<use>` references, CSS, units such as `ex`,
+/// and inlined the images it could load, so the result renders the same
+/// anywhere. Text stays text, using the fonts named in the SVG, unless
+/// `text_to_paths` is set. The `frame` adds a margin, padding, a background
+/// and a border.
+pub fn normalize_svg(
+    tree: &usvg::Tree,
+    layout: &Layout,
+    frame: &Frame,
+    text_to_paths: bool,
+) -> Result<String> {
+    let svg = tree.to_string(&usvg::WriteOptions {
+        preserve_text: !text_to_paths,
+        coordinates_precision: 4,
+        transforms_precision: 6,
+        indent: usvg::Indent::None,
+        ..Default::default()
+    });
+
+    // The writer gives the natural size, so scale with a view box when another is requested
+    let size = tree.size();
+    let (width, height) = (layout.width as f32, layout.height as f32);
+    let natural = format!(
+        r#"<svg width="{}" height="{}""#,
+        size.width(),
+        size.height()
+    );
+    // Returning the SVG as written would silently drop the size, background and frame
+    let rest = svg
+        .strip_prefix(&natural)
+        .context("Unexpected root element in normalized SVG")?;
+    let end = rest
+        .find('>')
+        .context("Unterminated root element in normalized SVG")?;
+    let (attributes, body) = (&rest[..=end], &rest[end + 1..]);
+
+    if frame.offset() == 0.0 && frame.border.is_none() && layout.is_stretched() {
+        let mut root = if (width, height) == (size.width(), size.height()) {
+            natural
+        } else {
+            format!(
+                r#"<svg width="{}" height="{}" viewBox="0 0 {} {}""#,
+                width,
+                height,
+                size.width(),
+                size.height()
+            )
+        };
+        if layout.transform.sx != layout.transform.sy {
+            root.push_str(r#" preserveAspectRatio="none""#);
+        }
+        root.push_str(attributes);
+        if let (Some(c), Some(outline)) =
+            (frame.background, frame.background_outline(width, height))
+        {
+            // The view box is in SVG units, so map the outline from pixels
+            let to_view = usvg::Transform::from_scale(size.width() / width, size.height() / height);
+            if let Some(outline) = outline.transform(to_view) {
+                push_path(&mut root, &outline, "fill", c);
+                root.push_str("/>");
+            }
+        }
+        root.push_str(body);
+        return Ok(root);
+    }
+
+    // Otherwise the view box is in pixels, and the content is placed inside the margin and padding
+    let (outer_width, outer_height) = frame.size(width, height);
+    let mut root = format!(
+        r#"<svg width="{0}" height="{1}" viewBox="0 0 {0} {1}""#,
+        outer_width, outer_height
+    );
+    root.push_str(attributes);
+    let background = frame
+        .background
+        .zip(frame.background_outline(outer_width, outer_height));
+    if let Some((c, outline)) = background {
+        push_path(&mut root, &outline, "fill", c);
+        root.push_str("/>");
+    }
+    let body = body.strip_suffix("</svg>").unwrap_or(body);
+    let Transform { sx, sy, tx, ty, .. } = layout.transform;
+    // A nested viewport cuts off what overflows it
+    let (tx, ty) = if layout.clip {
+        root.push_str(&format!(
+            r#"<svg x="{0}" y="{0}" width="{1}" height="{2}">"#,
+            frame.offset(),
+            width,
+            height
+        ));
+        (tx, ty)
+    } else {
+        (frame.offset() + tx, frame.offset() + ty)
+    };
+    root.push_str(&format!(
+        r#"<g transform="matrix({} 0 0 {} {} {})">"#,
+        sx, sy, tx, ty
+    ));
+    root.push_str(body);
+    root.push_str("</g>");
+    if layout.clip {
+        root.push_str("</svg>");
+    }
+    let border = frame
+        .border
+        .zip(frame.border_outline(outer_width, outer_height));
+    if let Some(((c, border_width), outline)) = border {
+        push_path(&mut root, &outline, "stroke", c);
+        root.push_str(&format!(
+            r#" fill="none" stroke-width="{}"/>"#,
+            border_width
+        ));
+    }
+    root.push_str("</svg>");
+    Ok(root)
+}
+
+/// Start a `<path>` element painting `path` with `color`, as its `fill` or `stroke`
+pub(crate) fn push_path(
+    out: &mut String,
+    path: &tiny_skia::Path,
+    paint: &str,
+    color: tiny_skia::Color,
+) {
+    let c = color.to_color_u8();
+    out.push_str(r#"<path d=""#);
+    for (i, segment) in path.segments().enumerate() {
+        if i > 0 {
+            out.push(' ');
+        }
+        match segment {
+            PathSegment::MoveTo(p) => out.push_str(&format!("M{} {}", p.x, p.y)),
+            PathSegment::LineTo(p) => out.push_str(&format!("L{} {}", p.x, p.y)),
+            PathSegment::QuadTo(p1, p) => {
+                out.push_str(&format!("Q{} {} {} {}", p1.x, p1.y, p.x, p.y))
+            }
+            PathSegment::CubicTo(p1, p2, p) => out.push_str(&format!(
+                "C{} {} {} {} {} {}",
+                p1.x, p1.y, p2.x, p2.y, p.x, p.y
+            )),
+            PathSegment::Close => out.push('Z'),
+        }
+    }
+    out.push_str(&format!(
+        r#"" {}="rgb({},{},{})""#,
+        paint,
+        c.red(),
+        c.green(),
+        c.blue()
+    ));
+    if c.alpha() < 255 {
+        out.push_str(&format!(
+            r#" {}-opacity="{}""#,
+            paint,
+            c.alpha() as f32 / 255.0
+        ));
+    }
+}
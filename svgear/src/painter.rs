@@ -1,53 +1,301 @@
 use anyhow::Result;
+use async_trait::async_trait;
+use fxhash::FxHashMap;
 use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::time::Instant;
 
+use crate::disk_cache::{CacheKind, DiskCache};
+use crate::error::SvgearError;
+use crate::manager::cache::LruCache;
+use crate::manager::{CacheLimit, CacheUsage};
+use crate::single_flight::SingleFlight;
+
+mod node_pool;
 mod node_server;
 
+pub use node_pool::{NodeServerPool, PoolStats, DEFAULT_WORKERS};
 pub use node_server::NodeServer;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PaintParams {
     pub ty: PaintType,
     pub content: String,
+    /// Give up on the paint after this many milliseconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+}
+
+impl PaintParams {
+    /// Key of the painted SVG in caches, made of everything that affects the output
+    fn cache_key(&self) -> String {
+        serde_json::to_string(&(&self.ty, &self.content)).unwrap_or_default()
+    }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum PaintType {
     InlineTeX,
     Equation,
+    InlineMathML,
+    DisplayMathML,
+    InlineAsciiMath,
+    DisplayAsciiMath,
     Mermaid,
+    /// A content type handled by a user registered backend
+    Custom(String),
+}
+
+impl PaintType {
+    /// Paint types understood by the bundled Node.js server
+    pub const BUILTIN: [PaintType; 7] = [
+        PaintType::InlineTeX,
+        PaintType::Equation,
+        PaintType::InlineMathML,
+        PaintType::DisplayMathML,
+        PaintType::InlineAsciiMath,
+        PaintType::DisplayAsciiMath,
+        PaintType::Mermaid,
+    ];
+
+    /// Whether the content is typeset inline with surrounding text
+    pub fn is_inline(&self) -> bool {
+        matches!(
+            self,
+            PaintType::InlineTeX | PaintType::InlineMathML | PaintType::InlineAsciiMath
+        )
+    }
+}
+
+impl std::fmt::Display for PaintType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PaintType::InlineTeX => f.write_str("inlinetex"),
+            PaintType::Equation => f.write_str("equation"),
+            PaintType::InlineMathML => f.write_str("inlinemathml"),
+            PaintType::DisplayMathML => f.write_str("mathml"),
+            PaintType::InlineAsciiMath => f.write_str("inlineasciimath"),
+            PaintType::DisplayAsciiMath => f.write_str("asciimath"),
+            PaintType::Mermaid => f.write_str("mermaid"),
+            PaintType::Custom(name) => f.write_str(name),
+        }
+    }
+}
+
+impl std::str::FromStr for PaintType {
+    type Err = anyhow::Error;
+
+    /// Parse the names used by the CLI and the Emacs module
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "inlinetex" => Ok(PaintType::InlineTeX),
+            "equation" => Ok(PaintType::Equation),
+            "inlinemathml" => Ok(PaintType::InlineMathML),
+            "mathml" => Ok(PaintType::DisplayMathML),
+            "inlineasciimath" => Ok(PaintType::InlineAsciiMath),
+            "asciimath" => Ok(PaintType::DisplayAsciiMath),
+            "mermaid" => Ok(PaintType::Mermaid),
+            _ => Err(anyhow::anyhow!("Unsupported input type: {}", s)),
+        }
+    }
+}
+
+/// Point in time after which a paint is abandoned
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Deadline {
+    at: Instant,
+    timeout: Duration,
+}
+
+impl Deadline {
+    /// The deadline `timeout_ms` from now, if set
+    pub(crate) fn after(timeout_ms: Option<u64>) -> Option<Self> {
+        timeout_ms.map(|ms| {
+            let timeout = Duration::from_millis(ms);
+            Deadline {
+                at: Instant::now() + timeout,
+                timeout,
+            }
+        })
+    }
+}
+
+/// Run `operation`, failing with [`SvgearError::Timeout`] if it is still going at the deadline
+pub(crate) async fn within<T>(
+    deadline: Option<Deadline>,
+    operation: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline.at, operation)
+            .await
+            .map_err(|_| SvgearError::Timeout(deadline.timeout))?,
+        None => operation.await,
+    }
 }
 
+/// A renderer that turns [`PaintParams`] into SVG
+#[async_trait]
+pub trait PaintBackend: std::fmt::Debug + Send + Sync {
+    /// Paint content to SVG
+    async fn paint(&self, params: PaintParams) -> Result<String>;
+}
+
+/// Default limit of the in-memory paint memo, 16 MiB of SVG
+pub const DEFAULT_MEMO_LIMIT: CacheLimit = CacheLimit {
+    max_bytes: Some(16 << 20),
+    max_entries: None,
+};
+
 /// A painter that can render different types of content to SVG
+///
+/// Painted SVGs are memoized by paint type and content, so repeated
+/// requests do not reach the backend, and concurrent identical requests
+/// share a single paint.
 #[derive(Clone, Debug)]
 pub struct Painter {
-    node_server: Option<NodeServer>,
+    /// Backends keyed by the paint type they handle
+    backends: FxHashMap<PaintType, Arc<dyn PaintBackend>>,
+    /// Worker pool registered through [`Painter::set_node_pool`], kept for its stats
+    node_pool: Option<NodeServerPool>,
+    /// SVGs kept across restarts
+    disk_cache: Option<DiskCache>,
+    /// Recently painted SVGs, shared between clones
+    memo: Arc<RwLock<LruCache<String>>>,
+    /// Paints in progress, shared between clones
+    inflight: SingleFlight<String>,
+}
+
+impl Default for Painter {
+    fn default() -> Self {
+        Painter {
+            backends: FxHashMap::default(),
+            node_pool: None,
+            disk_cache: None,
+            memo: Arc::new(RwLock::new(LruCache::new(DEFAULT_MEMO_LIMIT))),
+            inflight: SingleFlight::new(),
+        }
+    }
 }
 
 impl Painter {
-    /// Create a new painter
+    /// Create a new painter without any backend
     pub fn new() -> Self {
-        Painter { node_server: None }
+        Self::default()
     }
 
     /// Create a new painter with a Node server
     pub fn with_node_server(exe_path: String) -> Self {
         let mut painter = Self::new();
-        painter.node_server = Some(NodeServer::new(exe_path));
+        painter.set_node_server(NodeServer::new(exe_path));
+        painter
+    }
+
+    /// Create a new painter with a pool of `workers` Node servers
+    pub fn with_node_pool(exe_path: String, workers: usize) -> Self {
+        let mut painter = Self::new();
+        painter.set_node_pool(NodeServerPool::new(exe_path, workers));
         painter
     }
 
-    /// Set the Node server
+    /// Set the Node server for every paint type it supports
     pub fn set_node_server(&mut self, server: NodeServer) {
-        self.node_server = Some(server);
+        let server = Arc::new(server);
+        for ty in PaintType::BUILTIN {
+            self.backends.insert(ty, server.clone());
+        }
+    }
+
+    /// Set a Node server pool for every paint type it supports
+    pub fn set_node_pool(&mut self, pool: NodeServerPool) {
+        let backend = Arc::new(pool.clone());
+        for ty in PaintType::BUILTIN {
+            self.backends.insert(ty, backend.clone());
+        }
+        self.node_pool = Some(pool);
+    }
+
+    /// Load on the Node server pool, if one is set
+    pub fn pool_stats(&self) -> Option<PoolStats> {
+        self.node_pool.as_ref().map(NodeServerPool::stats)
+    }
+
+    /// Look up and store painted SVGs in a cache on disk
+    pub fn set_disk_cache(&mut self, disk_cache: Option<DiskCache>) {
+        self.disk_cache = disk_cache;
+    }
+
+    /// Change the limit of the in-memory paint memo, evicting entries as needed
+    pub fn set_memo_limit(&self, limit: CacheLimit) {
+        self.memo.write().unwrap().set_limit(limit);
+    }
+
+    /// Number of entries, bytes and evictions of the in-memory paint memo
+    pub fn memo_stats(&self) -> CacheUsage {
+        self.memo.read().unwrap().usage()
+    }
+
+    /// Register a backend for a paint type, replacing any previous one
+    pub fn register_backend(&mut self, ty: PaintType, backend: Arc<dyn PaintBackend>) {
+        self.backends.insert(ty, backend);
+    }
+
+    /// Get the backend registered for a paint type
+    pub fn backend(&self, ty: &PaintType) -> Option<&Arc<dyn PaintBackend>> {
+        self.backends.get(ty)
     }
 
     /// Paint content to SVG
+    ///
+    /// The timeout of `params` applies to this call alone, also while it
+    /// waits on an identical paint started by another call.
     pub async fn paint(&self, params: PaintParams) -> Result<String> {
-        self.node_server
-            .as_ref()
-            .ok_or(anyhow::anyhow!("No Node server configured"))?
-            .paint(params)
-            .await
+        let backend = self
+            .backend(&params.ty)
+            .ok_or_else(|| SvgearError::NoBackend(params.ty.clone()))?;
+
+        let key = params.cache_key();
+        if let Some(svg) = self.memo.read().unwrap().get(&key) {
+            return Ok(svg.clone());
+        }
+
+        let deadline = Deadline::after(params.timeout_ms);
+        let paint = self
+            .inflight
+            .run(&key, || self.paint_uncached(backend, &key, params));
+        let svg = within(deadline, paint).await?;
+
+        let bytes = key.len() + svg.len();
+        self.memo.write().unwrap().insert(key, svg.clone(), bytes);
+        Ok(svg)
+    }
+
+    /// Paint content missing from the memo, going through the disk cache if set
+    async fn paint_uncached(
+        &self,
+        backend: &Arc<dyn PaintBackend>,
+        key: &str,
+        params: PaintParams,
+    ) -> Result<String> {
+        // The disk is only touched from the blocking thread pool
+        if let Some(disk_cache) = self.disk_cache.clone() {
+            let key = key.to_string();
+            let on_disk =
+                tokio::task::spawn_blocking(move || disk_cache.get(CacheKind::Svg, &key)).await?;
+            if let Some(svg) = on_disk.and_then(|data| String::from_utf8(data).ok()) {
+                return Ok(svg);
+            }
+        }
+
+        let svg = backend.paint(params).await?;
+        if let Some(disk_cache) = self.disk_cache.clone() {
+            let (key, svg) = (key.to_string(), svg.clone());
+            tokio::task::spawn_blocking(move || {
+                disk_cache.put(CacheKind::Svg, &key, svg.as_bytes())
+            })
+            .await?;
+        }
+        Ok(svg)
     }
 }
@@ -0,0 +1,149 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Semaphore;
+
+use super::{within, Deadline, NodeServer, PaintBackend, PaintParams};
+
+/// Default number of workers
+///
+/// Every worker runs its own MathJax instance, so more of them cost memory
+/// and start-up time; raise it for servers that paint a lot in parallel.
+pub const DEFAULT_WORKERS: usize = 1;
+
+/// Snapshot of the load on a [`NodeServerPool`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PoolStats {
+    /// Number of worker processes in the pool
+    pub workers: usize,
+    /// Workers currently painting
+    pub busy: usize,
+    /// Workers waiting for a request
+    pub idle: usize,
+    /// Requests waiting for a worker to become idle
+    pub queued: usize,
+}
+
+/// A pool of Node.js render processes
+///
+/// Every request is handed to an idle worker. When all workers are busy,
+/// requests wait in FIFO order for the next one to finish.
+#[derive(Clone)]
+pub struct NodeServerPool {
+    inner: Arc<PoolInner>,
+}
+
+struct PoolInner {
+    workers: Vec<NodeServer>,
+    /// Indices of the workers that are not painting
+    idle: Mutex<Vec<usize>>,
+    /// One permit per idle worker
+    permits: Semaphore,
+    /// Requests waiting for a permit
+    queued: AtomicUsize,
+}
+
+impl std::fmt::Debug for NodeServerPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NodeServerPool")
+            .field("workers", &self.inner.workers)
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+impl NodeServerPool {
+    /// Create a pool of `workers` processes running the script, started on first use
+    pub fn new(script_path: String, workers: usize) -> Self {
+        let workers = workers.max(1);
+        NodeServerPool {
+            inner: Arc::new(PoolInner {
+                workers: (0..workers)
+                    .map(|_| NodeServer::new(script_path.clone()))
+                    .collect(),
+                idle: Mutex::new((0..workers).rev().collect()),
+                permits: Semaphore::new(workers),
+                queued: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    /// Current load on the pool
+    pub fn stats(&self) -> PoolStats {
+        let workers = self.inner.workers.len();
+        let idle = self.inner.idle.lock().unwrap().len();
+        PoolStats {
+            workers,
+            busy: workers - idle,
+            idle,
+            queued: self.inner.queued.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Paint content to SVG on the next idle worker
+    ///
+    /// The timeout of the request includes the time it waits for a worker.
+    pub async fn paint(&self, params: PaintParams) -> Result<String> {
+        let deadline = Deadline::after(params.timeout_ms);
+        within(deadline, self.paint_on_worker(params, deadline)).await
+    }
+
+    async fn paint_on_worker(
+        &self,
+        params: PaintParams,
+        deadline: Option<Deadline>,
+    ) -> Result<String> {
+        let _permit = {
+            let _queued = Counted::new(&self.inner.queued);
+            self.inner.permits.acquire().await?
+        };
+
+        // A permit guarantees that an idle worker is available
+        let index = self.inner.idle.lock().unwrap().pop().unwrap();
+        // Dropped before the permit, so the worker is idle again once the permit is released
+        let _worker = IdleOnDrop {
+            inner: &self.inner,
+            index,
+        };
+        self.inner.workers[index]
+            .paint_until(params, deadline)
+            .await
+    }
+}
+
+/// Keeps a counter incremented while alive
+struct Counted<'a>(&'a AtomicUsize);
+
+impl<'a> Counted<'a> {
+    fn new(counter: &'a AtomicUsize) -> Self {
+        counter.fetch_add(1, Ordering::Relaxed);
+        Counted(counter)
+    }
+}
+
+impl Drop for Counted<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Returns a worker to the idle list, even if the paint future is dropped
+struct IdleOnDrop<'a> {
+    inner: &'a PoolInner,
+    index: usize,
+}
+
+impl Drop for IdleOnDrop<'_> {
+    fn drop(&mut self) {
+        self.inner.idle.lock().unwrap().push(self.index);
+    }
+}
+
+#[async_trait]
+impl PaintBackend for NodeServerPool {
+    async fn paint(&self, params: PaintParams) -> Result<String> {
+        NodeServerPool::paint(self, params).await
+    }
+}
@@ -1,24 +1,70 @@
 use anyhow::{Context, Result};
+use async_trait::async_trait;
+use fxhash::FxHashMap;
 use serde::{Deserialize, Serialize};
-use std::io::{BufRead, BufReader, Read, Write};
-use std::process::{Child, Command, Stdio};
-use std::sync::Arc;
-use tokio::sync::Mutex as AsyncMutex;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStderr, ChildStdin, ChildStdout, Command};
+use tokio::sync::{oneshot, Mutex as AsyncMutex};
 
-use super::{PaintParams, PaintType};
+use super::{within, Deadline, PaintBackend, PaintParams, PaintType};
+use crate::error::{PaintError, SvgearError};
 
-/// Request to the Node.js server
+/// Delay before restarting a crashed process, doubled for every consecutive crash
+const RESTART_BACKOFF_BASE: Duration = Duration::from_millis(100);
+/// Upper bound for the restart delay
+const RESTART_BACKOFF_MAX: Duration = Duration::from_secs(10);
+
+/// Request to the Node.js server, sent as one line of JSON
 #[derive(Debug, Serialize, Deserialize)]
 struct NodeRequest {
+    /// Id echoed back in the matching response
+    id: u64,
     /// The type of content to render
     #[serde(rename = "method")]
     ty: String,
+    /// Input format of the equation: `TeX`, `MathML` or `AsciiMath` (for MathJax)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    format: Option<String>,
     /// Whether the equation is inline or display mode (for MathJax)
     inline: bool,
     /// The content to render
     content: String,
 }
 
+/// Response from the Node.js server, received as one line of JSON
+///
+/// Either `{"id": 1, "ok": "<svg ...>"}` or
+/// `{"id": 1, "error": {"message": "...", "kind": "tex", "line": 1, "column": 12}}`
+/// where everything but the message is optional.
+/// The id is `null` when the request could not be parsed at all.
+#[derive(Debug, Deserialize)]
+struct NodeResponse {
+    id: Option<u64>,
+    #[serde(flatten)]
+    body: NodeResponseBody,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum NodeResponseBody {
+    Ok(String),
+    Error(PaintError),
+}
+
+/// Requests waiting for their response, keyed by request id
+#[derive(Default)]
+struct PendingRequests {
+    senders: FxHashMap<u64, oneshot::Sender<Result<String>>>,
+    /// Set once stdout is closed and no more responses can arrive
+    closed: bool,
+}
+
+type Pending = Arc<Mutex<PendingRequests>>;
+
 /// Configuration for the Node.js server
 #[derive(Clone)]
 pub struct NodeServer {
@@ -26,6 +72,14 @@ pub struct NodeServer {
     script_path: String,
     /// Child process handle
     process: Arc<AsyncMutex<Option<ChildProcess>>>,
+    /// Id for the next request
+    next_id: Arc<AtomicU64>,
+    /// Crashes since the last successful response, drives the restart backoff
+    crashes: Arc<AtomicU32>,
+    /// Total number of restarts after a crash
+    restarts: Arc<AtomicU64>,
+    /// Total number of processes killed because a request was abandoned
+    recycles: Arc<AtomicU64>,
 }
 
 impl std::fmt::Debug for NodeServer {
@@ -37,12 +91,16 @@ impl std::fmt::Debug for NodeServer {
 /// Wrapper for the child process
 struct ChildProcess {
     child: Child,
+    /// Process id, kept because `Child::id` is gone once the process is reaped
+    pid: u32,
+    stdin: ChildStdin,
+    pending: Pending,
 }
 
-impl Drop for ChildProcess {
-    fn drop(&mut self) {
-        // Attempt to kill the process when it's dropped
-        let _ = self.child.kill();
+impl ChildProcess {
+    /// Whether the process is still running and its stdout is open
+    fn is_alive(&mut self) -> bool {
+        !self.pending.lock().unwrap().closed && matches!(self.child.try_wait(), Ok(None))
     }
 }
 
@@ -52,13 +110,55 @@ impl NodeServer {
         NodeServer {
             script_path,
             process: Arc::new(AsyncMutex::new(None)),
+            next_id: Arc::new(AtomicU64::new(0)),
+            crashes: Arc::new(AtomicU32::new(0)),
+            restarts: Arc::new(AtomicU64::new(0)),
+            recycles: Arc::new(AtomicU64::new(0)),
         }
     }
 
-    /// Start the Node.js server process if not already running
+    /// Number of times the process was restarted after a crash
+    pub fn restarts(&self) -> u64 {
+        self.restarts.load(Ordering::Relaxed)
+    }
+
+    /// Number of times the process was killed because a request was abandoned
+    pub fn recycles(&self) -> u64 {
+        self.recycles.load(Ordering::Relaxed)
+    }
+
+    /// Start the Node.js server process if not already running, restarting it if it died
     async fn ensure_process_started(&self) -> Result<()> {
         let mut process_guard = self.process.lock().await;
 
+        if let Some(process) = process_guard.as_mut() {
+            if process.is_alive() {
+                return Ok(());
+            }
+
+            let status = process.child.try_wait().ok().flatten();
+            *process_guard = None;
+
+            let crashes = self.crashes.fetch_add(1, Ordering::Relaxed);
+            let delay = RESTART_BACKOFF_BASE
+                .saturating_mul(1 << crashes.min(16))
+                .min(RESTART_BACKOFF_MAX);
+            log::warn!(
+                "Node.js server {} exited ({}), restarting in {:?}",
+                self.script_path,
+                status.map_or("unknown status".to_string(), |s| s.to_string()),
+                delay
+            );
+            // Other requests wait on the lock instead of spawning in a tight loop
+            tokio::time::sleep(delay).await;
+            let restarts = self.restarts.fetch_add(1, Ordering::Relaxed) + 1;
+            log::info!(
+                "Restarting Node.js server {} (restart #{})",
+                self.script_path,
+                restarts
+            );
+        }
+
         if process_guard.is_none() {
             // Start the Node.js process with stdio mode
             let mut child = Command::new(&self.script_path)
@@ -66,6 +166,8 @@ impl NodeServer {
                 .stdin(Stdio::piped())
                 .stdout(Stdio::piped())
                 .stderr(Stdio::piped())
+                // Kill the process when its ChildProcess is dropped
+                .kill_on_drop(true)
                 .spawn()
                 .context("Failed to start Node.js server process")?;
 
@@ -75,20 +177,37 @@ impl NodeServer {
                 let mut line = String::new();
 
                 // Read the first line which should contain startup message
-                if reader.read_line(&mut line).is_ok() {
-                    if !line.contains("Running in stdio mode") {
-                        return Err(anyhow::anyhow!(
-                            "Unexpected output from Node.js server: {}",
-                            line
-                        ));
-                    }
+                if reader.read_line(&mut line).await.is_ok()
+                    && !line.contains("Running in stdio mode")
+                {
+                    return Err(anyhow::anyhow!(
+                        "Unexpected output from Node.js server: {}",
+                        line
+                    ));
                 }
 
-                // Put stderr back
-                child.stderr = Some(reader.into_inner());
+                // Keep draining stderr so the process never blocks on a full pipe
+                tokio::spawn(log_stderr(reader));
             }
 
-            *process_guard = Some(ChildProcess { child });
+            let stdin = child
+                .stdin
+                .take()
+                .ok_or_else(|| anyhow::anyhow!("Failed to get stdin handle"))?;
+            let stdout = child
+                .stdout
+                .take()
+                .ok_or_else(|| anyhow::anyhow!("Failed to get stdout handle"))?;
+
+            let pending = Pending::default();
+            tokio::spawn(read_responses(stdout, pending.clone()));
+
+            *process_guard = Some(ChildProcess {
+                pid: child.id().unwrap_or_default(),
+                child,
+                stdin,
+                pending,
+            });
         }
 
         Ok(())
@@ -96,77 +215,234 @@ impl NodeServer {
 
     /// Paint content to SVG
     pub async fn paint(&self, params: PaintParams) -> Result<String> {
+        let deadline = Deadline::after(params.timeout_ms);
+        self.paint_until(params, deadline).await
+    }
+
+    /// Paint content to SVG, giving up at the deadline
+    ///
+    /// The deadline covers everything from here on: waiting for the process,
+    /// restarting it after a crash and waiting for the response.
+    pub(crate) async fn paint_until(
+        &self,
+        params: PaintParams,
+        deadline: Option<Deadline>,
+    ) -> Result<String> {
+        within(deadline, self.paint_request(params)).await
+    }
+
+    async fn paint_request(&self, params: PaintParams) -> Result<String> {
         // Create the request based on the paint type
-        let request = match params.ty {
-            PaintType::InlineTeX => NodeRequest {
-                ty: "mathjax".to_string(),
-                inline: true,
-                content: params.content,
-            },
-            PaintType::Equation => NodeRequest {
-                ty: "mathjax".to_string(),
-                inline: false,
-                content: params.content,
-            },
-            PaintType::Mermaid => NodeRequest {
-                ty: "mermaid".to_string(),
-                inline: false, // Not used for Mermaid
-                content: params.content,
-            },
+        let (ty, format) = match &params.ty {
+            PaintType::InlineTeX | PaintType::Equation => ("mathjax", Some("TeX")),
+            PaintType::InlineMathML | PaintType::DisplayMathML => ("mathjax", Some("MathML")),
+            PaintType::InlineAsciiMath | PaintType::DisplayAsciiMath => {
+                ("mathjax", Some("AsciiMath"))
+            }
+            PaintType::Mermaid => ("mermaid", None),
+            PaintType::Custom(name) => {
+                return Err(anyhow::anyhow!("Node.js server cannot paint {}", name));
+            }
+        };
+        let request = NodeRequest {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            ty: ty.to_string(),
+            format: format.map(String::from),
+            inline: params.ty.is_inline(),
+            content: params.content,
+        };
+
+        // Serialize the request to a single line of JSON
+        let mut request_json =
+            serde_json::to_vec(&request).context("Failed to serialize Node.js request")?;
+        request_json.push(b'\n');
+
+        // A crash takes the in-flight request down with it, give it one more try
+        let result = match self.send(request.id, &request_json).await {
+            Err(e) if is_exited(&e) => {
+                log::warn!(
+                    "Node.js server {} crashed during request {}, retrying once",
+                    self.script_path,
+                    request.id
+                );
+                self.send(request.id, &request_json).await
+            }
+            result => result,
         };
 
+        // Any answer means the process is healthy again
+        if !matches!(&result, Err(e) if is_exited(e)) {
+            self.crashes.store(0, Ordering::Relaxed);
+        }
+        result
+    }
+
+    /// Send a serialized request and wait for the response with the same id
+    async fn send(&self, id: u64, request_json: &[u8]) -> Result<String> {
         // Ensure the process is started
         self.ensure_process_started().await?;
 
-        // Get a lock on the process
+        let (receiver, mut in_flight) = {
+            // Get a lock on the process
+            let mut process_guard = self.process.lock().await;
+            let process = process_guard
+                .as_mut()
+                .ok_or_else(|| anyhow::anyhow!("Node.js server process not started"))?;
+
+            // Register before writing so that a fast response can't be missed
+            let (sender, receiver) = oneshot::channel();
+            {
+                let mut pending = process.pending.lock().unwrap();
+                if pending.closed {
+                    return Err(SvgearError::NodeExited.into());
+                }
+                pending.senders.insert(id, sender);
+            }
+
+            // Write the request to stdin, failing here means the process is gone
+            let written = match process.stdin.write_all(request_json).await {
+                Ok(()) => process.stdin.flush().await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = written {
+                let mut pending = process.pending.lock().unwrap();
+                pending.senders.remove(&id);
+                pending.closed = true;
+                return Err(anyhow::Error::from(e).context(SvgearError::NodeExited));
+            }
+
+            let in_flight = InFlight {
+                server: self.clone(),
+                pid: process.pid,
+                id,
+                done: false,
+            };
+            (receiver, in_flight)
+        };
+
+        // Wait for the response with the same id
+        let response = receiver.await;
+        in_flight.done = true;
+        response.map_err(|_| SvgearError::NodeExited)?
+    }
+
+    /// Kill the process if it is still the one with `pid`, the next request starts a fresh one
+    async fn recycle(&self, pid: u32) {
         let mut process_guard = self.process.lock().await;
-        let process = process_guard
-            .as_mut()
-            .ok_or_else(|| anyhow::anyhow!("Node.js server process not started"))?;
-
-        // Get stdin and stdout handles
-        let stdin = process
-            .child
-            .stdin
-            .as_mut()
-            .ok_or_else(|| anyhow::anyhow!("Failed to get stdin handle"))?;
-        let stdout = process
-            .child
-            .stdout
-            .as_mut()
-            .ok_or_else(|| anyhow::anyhow!("Failed to get stdout handle"))?;
-
-        // Serialize the request to JSON and send it
-        let request_json =
-            serde_json::to_string(&request).context("Failed to serialize Node.js request")?;
-
-        // Write the request to stdin
-        stdin
-            .write_all(request_json.as_bytes())
-            .context("Failed to write to Node.js server stdin")?;
-        stdin
-            .write_all(b"\n")
-            .context("Failed to write newline to Node.js server stdin")?;
-        stdin
-            .flush()
-            .context("Failed to flush Node.js server stdin")?;
-
-        // Read the response from stdout
-        let mut reader = BufReader::new(stdout);
-
-        // let mut response = String::new();
-        // reader.read_line(&mut response)
-        //     .context("Failed to read from Node.js server stdout")?;
-        let mut buf = vec![0; 1024 * 1024];
-        let bytes_read = reader.read(&mut buf)?;
-        let mut string_bytes = Vec::with_capacity(bytes_read);
-        string_bytes.extend_from_slice(&buf[..bytes_read]);
-        let response = String::from_utf8(string_bytes)?;
-
-        // Trim any whitespace
-        let svg_content = response.trim().to_string();
-
-        // Return the SVG content
-        Ok(svg_content)
+        self.recycle_locked(&mut process_guard, pid);
+    }
+
+    /// Like [`NodeServer::recycle`], with the process lock already held
+    fn recycle_locked(&self, process: &mut Option<ChildProcess>, pid: u32) {
+        if process.as_ref().is_some_and(|p| p.pid == pid) {
+            *process = None;
+            self.recycles.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// A request written to a process and not answered yet
+///
+/// A request that is abandoned, because it ran past its deadline or its future was dropped,
+/// may still keep the process busy, so the process is killed and replaced.
+struct InFlight {
+    server: NodeServer,
+    pid: u32,
+    id: u64,
+    done: bool,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        log::warn!(
+            "Node.js request {} abandoned, recycling process {}",
+            self.id,
+            self.pid
+        );
+        // Recycle right away if nothing holds the process, so the next request gets a fresh one
+        if let Ok(mut process_guard) = self.server.process.try_lock() {
+            self.server.recycle_locked(&mut process_guard, self.pid);
+            return;
+        }
+        let server = self.server.clone();
+        let pid = self.pid;
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            handle.spawn(async move { server.recycle(pid).await });
+        }
+    }
+}
+
+/// Whether an error means the Node.js process died
+fn is_exited(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<SvgearError>(),
+        Some(SvgearError::NodeExited)
+    )
+}
+
+/// Forward everything the process writes to stderr to the log
+async fn log_stderr(reader: BufReader<ChildStderr>) {
+    let mut lines = reader.lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        log::debug!("Node.js server: {}", line);
+    }
+}
+
+/// Read newline delimited responses from stdout and hand them to the waiting requests
+async fn read_responses(stdout: ChildStdout, pending: Pending) {
+    let mut lines = BufReader::new(stdout).lines();
+    loop {
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(e) => {
+                log::error!("Failed to read from Node.js server stdout: {}", e);
+                break;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response: NodeResponse = match serde_json::from_str(&line) {
+            Ok(response) => response,
+            Err(e) => {
+                log::warn!("Malformed response from Node.js server: {}: {}", e, line);
+                continue;
+            }
+        };
+
+        let result = match response.body {
+            NodeResponseBody::Ok(svg) => Ok(svg.trim().to_string()),
+            NodeResponseBody::Error(error) => Err(SvgearError::Paint(error).into()),
+        };
+
+        let Some(id) = response.id else {
+            log::warn!("Node.js server response without request id: {:?}", result);
+            continue;
+        };
+        match pending.lock().unwrap().senders.remove(&id) {
+            Some(sender) => {
+                let _ = sender.send(result);
+            }
+            None => log::warn!("Node.js server response for unknown request {}", id),
+        }
+    }
+
+    // Process is gone, fail everything still waiting
+    let mut pending = pending.lock().unwrap();
+    pending.closed = true;
+    for (_, sender) in pending.senders.drain() {
+        let _ = sender.send(Err(SvgearError::NodeExited.into()));
+    }
+}
+
+#[async_trait]
+impl PaintBackend for NodeServer {
+    async fn paint(&self, params: PaintParams) -> Result<String> {
+        NodeServer::paint(self, params).await
     }
 }
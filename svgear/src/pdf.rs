@@ -0,0 +1,26 @@
+use anyhow::Result;
+use resvg::usvg;
+
+use crate::fit::Layout;
+use crate::frame::Frame;
+use crate::normalize::normalize_svg;
+
+/// CSS pixels per inch, making a pixel three quarters of a PDF point
+const DPI: f32 = 96.0;
+
+/// Convert a parsed SVG to a single page PDF, placed on the page by the layout in CSS pixels
+///
+/// The SVG is normalized first, which places it inside the `frame` and
+/// turns text into paths, so the page needs no fonts. svg2pdf keeps
+/// shapes, gradients, patterns, masks and clip paths as vector graphics
+/// and only rasterizes filters.
+pub fn tree_to_pdf(tree: &usvg::Tree, layout: &Layout, frame: &Frame) -> Result<Vec<u8>> {
+    let svg = normalize_svg(tree, layout, frame, true)?;
+    let tree = usvg::Tree::from_str(&svg, &usvg::Options::default())?;
+    let options = svg2pdf::ConversionOptions {
+        embed_text: false,
+        ..svg2pdf::ConversionOptions::default()
+    };
+    svg2pdf::to_pdf(&tree, options, svg2pdf::PageOptions { dpi: DPI })
+        .map_err(|e| anyhow::anyhow!("Failed to convert to PDF: {}", e))
+}
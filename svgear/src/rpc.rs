@@ -1,4 +1,9 @@
-use crate::manager::{GetBitmapRequest, RenderRequest, SharedSvgManager};
+use crate::error::SvgearError;
+use crate::fonts::FontConfig;
+use crate::manager::{
+    GetBitmapRequest, GetBitmapResponse, RenderOptions, RenderRequest, SharedSvgManager, SvgManager,
+};
+use crate::metrics::SvgMetrics;
 use crate::painter::{PaintParams, Painter};
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
@@ -12,6 +17,9 @@ pub enum Method {
     GetBitmap,
     Paint,
     RenderToBitmap,
+    GetPoolStats,
+    GetCacheStats,
+    SetFonts,
 }
 
 /// Generic RPC request
@@ -27,6 +35,9 @@ pub struct RpcRequest<T> {
 pub struct RpcResponse<T> {
     pub result: Option<T>,
     pub error: Option<String>,
+    /// Machine readable kind of the error, e.g. `timeout`, when it is known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
     pub id: Option<String>,
 }
 
@@ -34,6 +45,16 @@ pub struct RpcResponse<T> {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PaintResult {
     pub svg: String,
+    /// Size and baseline position in pixels, if the SVG declares them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<SvgMetrics>,
+}
+
+/// Result of a SetFonts request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FontsResult {
+    /// Number of font faces now available
+    pub faces: usize,
 }
 
 /// Parameters for RenderToBitmap
@@ -42,6 +63,12 @@ pub struct RenderToBitmapParams {
     pub paint_params: PaintParams,
     pub width: Option<u32>,
     pub height: Option<u32>,
+    /// Colors, frame, size fitting, scale and format to render with
+    #[serde(flatten)]
+    pub options: RenderOptions,
+    /// Render an error image instead of failing when painting fails
+    #[serde(default)]
+    pub error_image: bool,
 }
 
 /// RPC server for SVG rendering
@@ -94,15 +121,17 @@ async fn handle_render_svg(
     server: &RpcServer,
     request_id: Option<String>,
 ) -> Json {
-    match server.manager.process_render_request(params) {
+    match server.manager.render(params).await {
         Ok(response) => json(&RpcResponse {
             result: Some(response),
             error: None,
+            kind: None,
             id: request_id,
         }),
         Err(e) => json(&RpcResponse::<()> {
             result: None,
             error: Some(format!("Error rendering SVG: {}", e)),
+            kind: SvgearError::kind_of(&e).map(String::from),
             id: request_id,
         }),
     }
@@ -118,11 +147,13 @@ async fn handle_get_bitmap(
         Ok(response) => json(&RpcResponse {
             result: Some(response),
             error: None,
+            kind: None,
             id: request_id,
         }),
         Err(e) => json(&RpcResponse::<()> {
             result: None,
             error: Some(format!("Error getting bitmap: {}", e)),
+            kind: SvgearError::kind_of(&e).map(String::from),
             id: request_id,
         }),
     }
@@ -132,13 +163,74 @@ async fn handle_get_bitmap(
 async fn handle_paint(params: PaintParams, server: &RpcServer, request_id: Option<String>) -> Json {
     match server.painter.paint(params).await {
         Ok(svg) => json(&RpcResponse {
-            result: Some(PaintResult { svg }),
+            result: Some(PaintResult {
+                metrics: SvgMetrics::from_svg(&svg),
+                svg,
+            }),
             error: None,
+            kind: None,
             id: request_id,
         }),
         Err(e) => json(&RpcResponse::<()> {
             result: None,
             error: Some(format!("Error painting: {}", e)),
+            kind: SvgearError::kind_of(&e).map(String::from),
+            id: request_id,
+        }),
+    }
+}
+
+/// Handle GetPoolStats requests
+async fn handle_get_pool_stats(server: &RpcServer, request_id: Option<String>) -> Json {
+    match server.painter.pool_stats() {
+        Some(stats) => json(&RpcResponse {
+            result: Some(stats),
+            error: None,
+            kind: None,
+            id: request_id,
+        }),
+        None => json(&RpcResponse::<()> {
+            result: None,
+            error: Some("No worker pool configured".to_string()),
+            kind: None,
+            id: request_id,
+        }),
+    }
+}
+
+/// Handle GetCacheStats requests
+async fn handle_get_cache_stats(server: &RpcServer, request_id: Option<String>) -> Json {
+    json(&RpcResponse {
+        result: Some(server.manager.stats()),
+        error: None,
+        kind: None,
+        id: request_id,
+    })
+}
+
+/// Handle SetFonts requests
+async fn handle_set_fonts(
+    params: FontConfig,
+    server: &RpcServer,
+    request_id: Option<String>,
+) -> Json {
+    // Loading system fonts reads every font file, so keep it off the async workers
+    let manager = server.manager.clone();
+    let result = tokio::task::spawn_blocking(move || manager.set_fonts(params))
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|result| result);
+    match result {
+        Ok(faces) => json(&RpcResponse {
+            result: Some(FontsResult { faces }),
+            error: None,
+            kind: None,
+            id: request_id,
+        }),
+        Err(e) => json(&RpcResponse::<()> {
+            result: None,
+            error: Some(format!("Error loading fonts: {}", e)),
+            kind: SvgearError::kind_of(&e).map(String::from),
             id: request_id,
         }),
     }
@@ -150,13 +242,21 @@ async fn handle_render_to_bitmap(
     server: &RpcServer,
     request_id: Option<String>,
 ) -> Json {
-    // Step 1: Paint to SVG
+    // Step 1: Paint to SVG, or to an error image if asked for
+    let mut paint_error = None;
     let paint_result = match server.painter.paint(params.paint_params).await {
         Ok(svg) => svg,
+        Err(e) if params.error_image => {
+            let message = e.to_string();
+            let svg = SvgManager::error_svg(&message);
+            paint_error = Some(message);
+            svg
+        }
         Err(e) => {
             return json(&RpcResponse::<()> {
                 result: None,
                 error: Some(format!("Error painting: {}", e)),
+                kind: SvgearError::kind_of(&e).map(String::from),
                 id: request_id,
             })
         }
@@ -168,34 +268,24 @@ async fn handle_render_to_bitmap(
         width: params.width,
         height: params.height,
         id: None,
+        options: params.options,
     };
 
-    match server.manager.process_render_request(render_request) {
-        Ok(render_response) => {
-            // Step 3: Get the bitmap
-            let get_bitmap_request = GetBitmapRequest {
+    match server.manager.render(render_request).await {
+        Ok(render_response) => json(&RpcResponse {
+            result: Some(GetBitmapResponse {
                 id: render_response.id,
-            };
-
-            match server
-                .manager
-                .process_get_bitmap_request(get_bitmap_request)
-            {
-                Ok(bitmap_response) => json(&RpcResponse {
-                    result: Some(bitmap_response),
-                    error: None,
-                    id: request_id,
-                }),
-                Err(e) => json(&RpcResponse::<()> {
-                    result: None,
-                    error: Some(format!("Error getting bitmap: {}", e)),
-                    id: request_id,
-                }),
-            }
-        }
+                bitmap: render_response.bitmap,
+                paint_error,
+            }),
+            error: None,
+            kind: None,
+            id: request_id,
+        }),
         Err(e) => json(&RpcResponse::<()> {
             result: None,
             error: Some(format!("Error rendering SVG: {}", e)),
+            kind: SvgearError::kind_of(&e).map(String::from),
             id: request_id,
         }),
     }
@@ -212,10 +302,14 @@ async fn handle_rpc(
         Some("GetBitmap") => Method::GetBitmap,
         Some("Paint") => Method::Paint,
         Some("RenderToBitmap") => Method::RenderToBitmap,
+        Some("GetPoolStats") => Method::GetPoolStats,
+        Some("GetCacheStats") => Method::GetCacheStats,
+        Some("SetFonts") => Method::SetFonts,
         _ => {
             return Ok(json(&RpcResponse::<()> {
                 result: None,
                 error: Some("Unknown method".to_string()),
+                kind: Some("unknown_method".to_string()),
                 id: request
                     .get("id")
                     .and_then(|id| id.as_str())
@@ -244,6 +338,7 @@ async fn handle_rpc(
                     return Ok(json(&RpcResponse::<()> {
                         result: None,
                         error: Some(format!("Invalid parameters: {}", e)),
+                        kind: Some("invalid_params".to_string()),
                         id: request_id,
                     }));
                 }
@@ -263,6 +358,7 @@ async fn handle_rpc(
                     return Ok(json(&RpcResponse::<()> {
                         result: None,
                         error: Some(format!("Invalid parameters: {}", e)),
+                        kind: Some("invalid_params".to_string()),
                         id: request_id,
                     }));
                 }
@@ -282,6 +378,7 @@ async fn handle_rpc(
                     return Ok(json(&RpcResponse::<()> {
                         result: None,
                         error: Some(format!("Invalid parameters: {}", e)),
+                        kind: Some("invalid_params".to_string()),
                         id: request_id,
                     }));
                 }
@@ -301,6 +398,7 @@ async fn handle_rpc(
                     return Ok(json(&RpcResponse::<()> {
                         result: None,
                         error: Some(format!("Invalid parameters: {}", e)),
+                        kind: Some("invalid_params".to_string()),
                         id: request_id,
                     }));
                 }
@@ -308,5 +406,27 @@ async fn handle_rpc(
 
             Ok(handle_render_to_bitmap(params, &server, request_id).await)
         }
+        Method::GetPoolStats => Ok(handle_get_pool_stats(&server, request_id).await),
+        Method::GetCacheStats => Ok(handle_get_cache_stats(&server, request_id).await),
+        Method::SetFonts => {
+            let params: FontConfig = match serde_json::from_value(
+                request
+                    .get("params")
+                    .cloned()
+                    .unwrap_or(serde_json::Value::Null),
+            ) {
+                Ok(p) => p,
+                Err(e) => {
+                    return Ok(json(&RpcResponse::<()> {
+                        result: None,
+                        error: Some(format!("Invalid parameters: {}", e)),
+                        kind: Some("invalid_params".to_string()),
+                        id: request_id,
+                    }));
+                }
+            };
+
+            Ok(handle_set_fonts(params, &server, request_id).await)
+        }
     }
 }
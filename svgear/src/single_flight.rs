@@ -0,0 +1,127 @@
+use anyhow::Result;
+use fxhash::FxHashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+
+use crate::error::SvgearError;
+
+/// Result of the leading call
+type Outcome<T> = Option<Result<T, Arc<anyhow::Error>>>;
+
+/// Coalesces concurrent calls with the same key into one
+///
+/// The first caller for a key does the work while later callers wait for
+/// its result. Errors reach every caller as [`SvgearError::Shared`], except
+/// timeouts, which only apply to the caller that set them. If the first
+/// caller is cancelled or times out, one of the waiting callers takes over.
+pub struct SingleFlight<T> {
+    inflight: Arc<Mutex<FxHashMap<String, watch::Receiver<Outcome<T>>>>>,
+}
+
+impl<T> Clone for SingleFlight<T> {
+    fn clone(&self) -> Self {
+        SingleFlight {
+            inflight: self.inflight.clone(),
+        }
+    }
+}
+
+impl<T> Default for SingleFlight<T> {
+    fn default() -> Self {
+        SingleFlight {
+            inflight: Arc::default(),
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for SingleFlight<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SingleFlight")
+            .field("inflight", &self.inflight.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl<T: Clone> SingleFlight<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of keys being worked on
+    pub fn inflight(&self) -> usize {
+        self.inflight.lock().unwrap().len()
+    }
+
+    /// Run `work` for `key`, unless a call for the same key is in flight, then share its result
+    pub async fn run<F, Fut>(&self, key: &str, work: F) -> Result<T>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        loop {
+            let waiting = {
+                let mut inflight = self.inflight.lock().unwrap();
+                match inflight.get(key) {
+                    Some(rx) => Ok(rx.clone()),
+                    None => {
+                        let (tx, rx) = watch::channel(None);
+                        inflight.insert(key.to_string(), rx);
+                        Err(tx)
+                    }
+                }
+            };
+
+            let mut rx = match waiting {
+                Ok(rx) => rx,
+                Err(tx) => return self.lead(key, tx, work).await,
+            };
+            // An error means the leader was cancelled, so try to take over
+            let outcome = match rx.wait_for(Option::is_some).await {
+                Ok(outcome) => outcome.clone(),
+                Err(_) => continue,
+            };
+            return match outcome {
+                Some(Ok(value)) => Ok(value),
+                Some(Err(error)) => Err(SvgearError::Shared(error).into()),
+                None => unreachable!(),
+            };
+        }
+    }
+
+    async fn lead<F, Fut>(&self, key: &str, tx: watch::Sender<Outcome<T>>, work: F) -> Result<T>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let _done = Done {
+            inflight: &self.inflight,
+            key,
+        };
+        match work().await {
+            Ok(value) => {
+                tx.send_replace(Some(Ok(value.clone())));
+                Ok(value)
+            }
+            // Dropping the sender lets a waiting caller take over
+            Err(e) if matches!(SvgearError::find(&e), Some(SvgearError::Timeout(_))) => Err(e),
+            Err(e) => {
+                let e = Arc::new(e);
+                tx.send_replace(Some(Err(e.clone())));
+                Err(SvgearError::Shared(e).into())
+            }
+        }
+    }
+}
+
+/// Removes the key once its leader finishes or is cancelled
+struct Done<'a, T> {
+    inflight: &'a Mutex<FxHashMap<String, watch::Receiver<Outcome<T>>>>,
+    key: &'a str,
+}
+
+impl<T> Drop for Done<'_, T> {
+    fn drop(&mut self) {
+        self.inflight.lock().unwrap().remove(self.key);
+    }
+}
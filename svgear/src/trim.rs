@@ -0,0 +1,114 @@
+use anyhow::Result;
+use resvg::{tiny_skia, usvg};
+use serde::{Deserialize, Serialize};
+
+/// How to find the area of an SVG that is rendered when trimming it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Trim {
+    /// The bounding box of everything drawn, including strokes and filter regions
+    Bounds,
+    /// The pixels that are not fully transparent, which also drops shapes
+    /// drawn with transparent paint
+    Transparent,
+}
+
+/// Largest width or height in pixels of the image that transparent pixels are found in
+const MAX_PROBE_SIZE: f32 = 4096.0;
+
+/// Top left corner of the rendered area in the untrimmed SVG, in pixels of its natural size
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TrimOffset {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl Trim {
+    /// The area of the SVG to render, in pixels of its natural size
+    ///
+    /// Transparent pixels are found at `scale` device pixels per pixel, or
+    /// less for SVGs that would be larger than [`MAX_PROBE_SIZE`] at it.
+    /// Returns `None` if nothing is drawn inside the SVG's own size.
+    pub(crate) fn content_rect(
+        self,
+        tree: &usvg::Tree,
+        scale: f32,
+    ) -> Result<Option<tiny_skia::Rect>> {
+        let size = tree.size();
+        let Some(viewport) = tiny_skia::Rect::from_xywh(0.0, 0.0, size.width(), size.height())
+        else {
+            return Ok(None);
+        };
+        let rect = match self {
+            Trim::Bounds => {
+                let root = tree.root();
+                root.has_children()
+                    .then(|| root.abs_layer_bounding_box().to_rect())
+            }
+            Trim::Transparent => {
+                let scale = scale.min(MAX_PROBE_SIZE / size.width().max(size.height()));
+                let (width, height) = (
+                    (size.width() * scale).ceil().max(1.0) as u32,
+                    (size.height() * scale).ceil().max(1.0) as u32,
+                );
+                let mut pixmap = tiny_skia::Pixmap::new(width, height)
+                    .ok_or_else(|| anyhow::anyhow!("Failed to create pixmap"))?;
+                resvg::render(
+                    tree,
+                    usvg::Transform::from_scale(scale, scale),
+                    &mut pixmap.as_mut(),
+                );
+                let Some((left, top, right, bottom)) = opaque_bounds(&pixmap) else {
+                    return Ok(None);
+                };
+                // Whole pixels, so the edges keep their anti-aliasing
+                tiny_skia::Rect::from_ltrb(
+                    left as f32 / scale,
+                    top as f32 / scale,
+                    (right + 1) as f32 / scale,
+                    (bottom + 1) as f32 / scale,
+                )
+            }
+        };
+        Ok(rect.and_then(|rect| rect.intersect(&viewport)))
+    }
+}
+
+/// Columns and rows of the first and last pixels that are not transparent
+fn opaque_bounds(pixmap: &tiny_skia::Pixmap) -> Option<(u32, u32, u32, u32)> {
+    let width = pixmap.width();
+    let mut bounds: Option<(u32, u32, u32, u32)> = None;
+    for (i, pixel) in pixmap.pixels().iter().enumerate() {
+        if pixel.alpha() == 0 {
+            continue;
+        }
+        let (x, y) = (i as u32 % width, i as u32 / width);
+        bounds = Some(match bounds {
+            None => (x, y, x, y),
+            Some((l, t, r, b)) => (l.min(x), t.min(y), r.max(x), b.max(y)),
+        });
+    }
+    bounds
+}
+
+impl std::fmt::Display for Trim {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Trim::Bounds => f.write_str("bounds"),
+            Trim::Transparent => f.write_str("transparent"),
+        }
+    }
+}
+
+impl std::str::FromStr for Trim {
+    type Err = anyhow::Error;
+
+    /// Parse the names used by the CLI
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "bounds" => Ok(Trim::Bounds),
+            "transparent" => Ok(Trim::Transparent),
+            _ => Err(anyhow::anyhow!("Unsupported trim: {}", s)),
+        }
+    }
+}
@@ -0,0 +1,36 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use svgear::error::{PaintError, SvgearError};
+use svgear::{PaintBackend, PaintParams};
+
+/// Counts how often it is asked to paint, and takes a while to fail on `fail`
+#[derive(Debug, Default)]
+pub struct CountingBackend(AtomicUsize);
+
+impl CountingBackend {
+    /// Number of paints so far
+    pub fn count(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[async_trait]
+impl PaintBackend for CountingBackend {
+    async fn paint(&self, params: PaintParams) -> Result<String> {
+        self.0.fetch_add(1, Ordering::Relaxed);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        if params.content == "fail" {
+            return Err(SvgearError::Paint(PaintError {
+                message: "Failed".to_string(),
+                kind: Some("test".to_string()),
+                line: Some(2),
+                column: Some(3),
+            })
+            .into());
+        }
+        Ok(format!("<svg>{}</svg>", params.content))
+    }
+}
@@ -0,0 +1,106 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use svgear::disk_cache::{CacheKind, CACHE_VERSION};
+use svgear::{DiskCache, PaintParams, PaintType, Painter, SvgManager};
+
+mod common;
+
+use common::CountingBackend;
+
+fn temp_dir() -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("svgear-test-{}", uuid::Uuid::new_v4()))
+}
+
+#[tokio::test]
+async fn test_paint_survives_restart() -> Result<()> {
+    let dir = temp_dir();
+    let ty = PaintType::Custom("count".to_string());
+    let params = PaintParams {
+        ty: ty.clone(),
+        content: "x".to_string(),
+        timeout_ms: None,
+    };
+
+    // Each painter stands for a separate run sharing the directory
+    let mut painters = Vec::new();
+    for _ in 0..2 {
+        let backend = Arc::new(CountingBackend::default());
+        let mut painter = Painter::new();
+        painter.register_backend(ty.clone(), backend.clone());
+        painter.set_disk_cache(Some(DiskCache::open(&dir, 1 << 20)?));
+        assert_eq!(painter.paint(params.clone()).await?, "<svg>x</svg>");
+        painters.push(backend);
+    }
+    assert_eq!(painters[0].count(), 1);
+    assert_eq!(painters[1].count(), 0);
+
+    std::fs::remove_dir_all(dir)?;
+    Ok(())
+}
+
+#[test]
+fn test_bitmap_survives_restart() -> Result<()> {
+    let dir = temp_dir();
+    let svg_data = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10"/>"#;
+
+    let mut first = SvgManager::new();
+    first.set_disk_cache(Some(DiskCache::open(&dir, 1 << 20)?));
+    let id = first.store_svg(svg_data, None);
+    first.render_svg(&id, Some(20), None)?;
+
+    // A custom id for the same content finds the bitmap on disk
+    let mut second = SvgManager::new();
+    second.set_disk_cache(Some(DiskCache::open(&dir, 1 << 20)?));
+    second.store_svg(svg_data, Some("again".to_string()));
+    assert_eq!(second.render_svg("again", Some(20), None)?, (20, 20));
+    assert_eq!(
+        second.get_bitmap("again").unwrap().data,
+        first.get_bitmap(&id).unwrap().data
+    );
+
+    std::fs::remove_dir_all(dir)?;
+    Ok(())
+}
+
+#[test]
+fn test_version_and_size_cap() -> Result<()> {
+    let dir = temp_dir();
+    let cache = DiskCache::open(&dir, 100)?;
+    let tag = std::fs::read(cache.root().join("CACHEDIR.TAG"))?;
+
+    // Only directories marked as an older cache are removed, nothing else in the directory
+    let stale = dir.join("svgear-cache/v0");
+    std::fs::create_dir_all(&stale)?;
+    std::fs::write(stale.join("CACHEDIR.TAG"), &tag)?;
+    // A newer version belongs to another svgear build sharing the directory
+    let newer = dir.join(format!("svgear-cache/v{}", CACHE_VERSION + 1));
+    std::fs::create_dir_all(&newer)?;
+    std::fs::write(newer.join("CACHEDIR.TAG"), &tag)?;
+    for unrelated in [dir.join("v1"), dir.join("svgear-cache/v9")] {
+        std::fs::create_dir_all(unrelated)?;
+    }
+    let cache = DiskCache::open(&dir, 100)?;
+    assert!(!stale.exists());
+    assert!(newer.exists());
+    assert!(dir.join("v1").exists());
+    assert!(dir.join("svgear-cache/v9").exists());
+
+    // Replacing an entry counts only its new size
+    cache.put(CacheKind::Svg, "a", &[0; 40]);
+    cache.put(CacheKind::Svg, "a", &[0; 30]);
+    assert_eq!(cache.bytes(), 30);
+    assert_eq!(cache.get(CacheKind::Svg, "a"), Some(vec![0; 30]));
+
+    for key in ["b", "c"] {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        cache.put(CacheKind::Svg, key, &[0; 40]);
+    }
+    // The oldest entry made way for the third
+    assert_eq!(cache.get(CacheKind::Svg, "a"), None);
+    assert_eq!(cache.get(CacheKind::Svg, "c"), Some(vec![0; 40]));
+    assert_eq!(cache.bytes(), 80);
+
+    std::fs::remove_dir_all(dir)?;
+    Ok(())
+}
@@ -0,0 +1,47 @@
+#!/usr/bin/env node
+// Stand-in for mathjax-svg-server speaking the same stdio protocol.
+// The content selects the behaviour:
+//   "slow:<text>"  answer after 200ms
+//   "fail:<text>"  answer with an error at column 6
+//   "crash"        exit without answering
+//   "hang"         never answer
+//   "pid"          answer with the process id
+//   anything else  answer immediately with <svg><text>text</text></svg>
+import { createInterface } from 'node:readline';
+
+function reply(response) {
+  process.stdout.write(JSON.stringify(response) + '\n');
+}
+
+console.error('Running in stdio mode');
+
+const rl = createInterface({ input: process.stdin, terminal: false });
+
+rl.on('line', async (line) => {
+  const request = JSON.parse(line);
+  const id = request.id;
+  let content = request.content;
+
+  if (content.startsWith('slow:')) {
+    content = content.slice(5);
+    await new Promise((resolve) => setTimeout(resolve, 200));
+  }
+
+  if (content === 'crash') {
+    process.exit(1);
+  }
+
+  if (content === 'hang') {
+    return;
+  }
+
+  if (content === 'pid') {
+    content = String(process.pid);
+  }
+
+  if (content.startsWith('fail:')) {
+    reply({ id, error: { message: content.slice(5), kind: 'tex', line: 1, column: 6 } });
+  } else {
+    reply({ id, ok: `<svg><text>${content}</text></svg>` });
+  }
+});
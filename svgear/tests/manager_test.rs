@@ -1,5 +1,31 @@
 use anyhow::Result;
-use svgear::SvgManager;
+use resvg::tiny_skia;
+use svgear::{
+    CacheLimit, CacheLimits, Fit, FontConfig, ImageFormat, RenderOptions, RenderRequest,
+    SharedSvgManager, SvgManager, Trim, TrimOffset,
+};
+
+/// A render request for `svg_data` without an id
+fn request(
+    svg_data: &str,
+    width: Option<u32>,
+    height: Option<u32>,
+    options: RenderOptions,
+) -> RenderRequest {
+    RenderRequest {
+        svg_data: svg_data.to_string(),
+        width,
+        height,
+        id: None,
+        options,
+    }
+}
+
+/// The RGBA bytes of the pixel at `x`, `y` of a raw RGBA bitmap
+fn pixel(bitmap: &svgear::manager::Bitmap, x: u32, y: u32) -> Vec<u8> {
+    let i = ((y * bitmap.width + x) * 4) as usize;
+    bitmap.data[i..i + 4].to_vec()
+}
 
 #[test]
 fn test_svg_manager() -> Result<()> {
@@ -35,3 +61,659 @@ fn test_svg_manager() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_error_svg() -> Result<()> {
+    let svg_data = SvgManager::error_svg("Undefined control sequence \\foo <x>");
+    assert!(svg_data.contains("\\foo &lt;x&gt;"));
+
+    let mut manager = SvgManager::new();
+    let id = manager.store_svg(&svg_data, None);
+    let (width, height) = manager.render_svg(&id, None, None)?;
+    assert_eq!(height, 18);
+    assert!(width > height);
+
+    // The baseline sits inside the box, like surrounding text
+    let metrics = manager.get_bitmap(&id).unwrap().metrics.unwrap();
+    assert_eq!(metrics.depth, 5.0);
+
+    // The message is drawn in red inside the box even without any fonts
+    manager.set_fonts(FontConfig {
+        system_fonts: false,
+        ..FontConfig::default()
+    })?;
+    let bitmap = manager
+        .process_render_request(request(
+            &svg_data,
+            None,
+            None,
+            RenderOptions {
+                format: Some(ImageFormat::Rgba),
+                ..RenderOptions::default()
+            },
+        ))?
+        .bitmap;
+    let inked = (3..bitmap.height - 3)
+        .flat_map(|y| (3..bitmap.width - 3).map(move |x| (x, y)))
+        .filter(|(x, y)| {
+            let i = ((y * bitmap.width + x) * 4) as usize;
+            let [r, g, b, _] = bitmap.data[i..i + 4] else {
+                unreachable!()
+            };
+            r > 150 && g < 100 && b < 100
+        })
+        .count();
+    assert!(inked > 100, "{inked} text pixels");
+
+    Ok(())
+}
+
+#[test]
+fn test_render_colors() -> Result<()> {
+    // The left half uses currentColor, the right half is left empty
+    let svg_data = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10">
+        <rect width="5" height="10" fill="currentColor" />
+    </svg>"#;
+    let request = |options| request(svg_data, None, None, options);
+
+    let mut manager = SvgManager::new();
+    let plain = manager.process_render_request(request(RenderOptions::default()))?;
+    let themed = manager.process_render_request(request(RenderOptions {
+        foreground: Some("#dcdccc".to_string()),
+        background: Some("rgb(63, 63, 63)".to_string()),
+        ..RenderOptions::default()
+    }))?;
+
+    // Each color scheme is cached separately
+    assert_ne!(plain.id, themed.id);
+    assert!(manager.get_bitmap(&plain.id).is_some());
+
+    let pixel = |data: &[u8], x| {
+        let pixmap = tiny_skia::Pixmap::decode_png(data).unwrap();
+        let c = pixmap.pixel(x, 5).unwrap();
+        (c.red(), c.green(), c.blue(), c.alpha())
+    };
+    assert_eq!(pixel(&plain.bitmap.data, 2), (0, 0, 0, 255));
+    assert_eq!(pixel(&plain.bitmap.data, 7), (0, 0, 0, 0));
+    assert_eq!(pixel(&themed.bitmap.data, 2), (0xdc, 0xdc, 0xcc, 255));
+    assert_eq!(pixel(&themed.bitmap.data, 7), (63, 63, 63, 255));
+
+    Ok(())
+}
+
+#[test]
+fn test_render_fonts() -> Result<()> {
+    let font = std::path::Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/fonts/DejaVuSansMono.ttf"
+    ));
+    let svg_data = r#"<svg xmlns="http://www.w3.org/2000/svg" width="40" height="20">
+        <text x="0" y="16" font-family="sans-serif" font-size="16">Hi</text>
+    </svg>"#;
+    let inked = |manager: &mut SvgManager| -> Result<usize> {
+        let resp = manager.process_render_request(request(
+            svg_data,
+            None,
+            None,
+            RenderOptions::default(),
+        ))?;
+        let pixmap = tiny_skia::Pixmap::decode_png(&resp.bitmap.data).unwrap();
+        Ok(pixmap.pixels().iter().filter(|p| p.alpha() > 0).count())
+    };
+
+    // Without any fonts the text is dropped
+    let mut manager = SvgManager::new();
+    manager.set_fonts(FontConfig {
+        system_fonts: false,
+        ..FontConfig::default()
+    })?;
+    assert_eq!(inked(&mut manager)?, 0);
+
+    manager.set_fonts(FontConfig {
+        system_fonts: false,
+        font_files: vec![font.to_path_buf()],
+        sans_serif_family: Some("DejaVu Sans Mono".to_string()),
+        ..FontConfig::default()
+    })?;
+    assert!(inked(&mut manager)? > 0);
+
+    // Missing paths are reported rather than ignored
+    assert!(manager
+        .set_fonts(FontConfig {
+            font_dirs: vec!["/nonexistent/fonts".into()],
+            ..FontConfig::default()
+        })
+        .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_render_formats() -> Result<()> {
+    // Half transparent red, to tell straight from premultiplied alpha
+    let svg_data = r#"<svg xmlns="http://www.w3.org/2000/svg" width="4" height="2">
+        <rect width="4" height="2" fill="red" fill-opacity="0.5" />
+    </svg>"#;
+    let mut manager = SvgManager::new();
+    let mut render = |format| {
+        manager.process_render_request(request(
+            svg_data,
+            None,
+            None,
+            RenderOptions {
+                format,
+                ..RenderOptions::default()
+            },
+        ))
+    };
+
+    let png = render(None)?.bitmap;
+    assert_eq!(png.format, ImageFormat::Png);
+    assert!(png.data.starts_with(b"\x89PNG"));
+    let webp = render(Some(ImageFormat::Webp))?.bitmap;
+    assert!(webp.data.starts_with(b"RIFF") && &webp.data[8..12] == b"WEBP");
+    let jpeg = render(Some(ImageFormat::Jpeg))?.bitmap;
+    assert!(jpeg.data.starts_with(&[0xff, 0xd8]));
+    let qoi = render(Some(ImageFormat::Qoi))?.bitmap;
+    assert!(qoi.data.starts_with(b"qoif"));
+
+    let straight = render(Some(ImageFormat::Rgba))?.bitmap;
+    assert_eq!(straight.stride, Some(16));
+    assert_eq!(straight.data.len(), 32);
+    assert_eq!(&straight.data[..4], &[255, 0, 0, 128]);
+    let premultiplied = render(Some(ImageFormat::RgbaPremultiplied))?.bitmap;
+    assert_eq!(&premultiplied.data[..4], &[128, 0, 0, 128]);
+    assert_eq!(png.stride, None);
+
+    // Only JPEG takes a quality, from 1 to 100
+    let mut render_quality = |format, quality| {
+        manager.process_render_request(request(
+            svg_data,
+            None,
+            None,
+            RenderOptions {
+                format,
+                quality: Some(quality),
+                ..RenderOptions::default()
+            },
+        ))
+    };
+    assert!(render_quality(Some(ImageFormat::Jpeg), 50).is_ok());
+    assert!(render_quality(Some(ImageFormat::Jpeg), 0).is_err());
+    assert!(render_quality(None, 200).is_err());
+    assert!(render_quality(Some(ImageFormat::Webp), 50).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_render_pdf() -> Result<()> {
+    let render_scaled = |svg_data: &str, scale| {
+        SvgManager::new().process_render_request(request(
+            svg_data,
+            None,
+            None,
+            RenderOptions {
+                format: Some(ImageFormat::Pdf),
+                scale,
+                ..RenderOptions::default()
+            },
+        ))
+    };
+    let render = |svg_data: &str| render_scaled(svg_data, None);
+    let contains = |data: &[u8], needle: &[u8]| data.windows(needle.len()).any(|w| w == needle);
+
+    // Solid shapes stay vector graphics on a page of the SVG's size in points
+    let svg_data = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="50" style="vertical-align: -1ex">
+        <rect width="50" height="50" fill="red" />
+    </svg>"#;
+    let plain = render(svg_data)?.bitmap;
+    assert_eq!(plain.format, ImageFormat::Pdf);
+    assert!(plain.data.starts_with(b"%PDF-"));
+    assert!(contains(&plain.data, b"/MediaBox [0 0 75 37.5]"));
+    assert!(!contains(&plain.data, b"/Subtype /Image"));
+    assert_eq!((plain.width, plain.height), (100, 50));
+
+    // The page and the reported size and metrics stay logical at any scale
+    let scaled = render_scaled(svg_data, Some(2.0))?.bitmap;
+    assert!(contains(&scaled.data, b"/MediaBox [0 0 75 37.5]"));
+    assert_eq!((scaled.width, scaled.height), (100, 50));
+    assert_eq!(scaled.metrics, plain.metrics);
+    assert_eq!(scaled.metrics.unwrap().depth, 6.0);
+
+    // Gradients stay vector graphics however large they are
+    let gradient = render(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="3000" height="3000">
+            <linearGradient id="g"><stop offset="0" stop-color="red"/><stop offset="1" stop-color="blue"/></linearGradient>
+            <rect width="3000" height="3000" fill="url(#g)" />
+        </svg>"#,
+    )?
+    .bitmap;
+    assert!(contains(&gradient.data, b"/ShadingType"));
+    assert!(!contains(&gradient.data, b"/Subtype /Image"));
+    assert!(gradient.data.len() < 16 << 10);
+
+    // Filters are rasterized
+    let blurred = render(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="50">
+            <filter id="f"><feGaussianBlur stdDeviation="2"/></filter>
+            <rect width="50" height="50" fill="red" filter="url(#f)" />
+        </svg>"#,
+    )?
+    .bitmap;
+    assert!(contains(&blurred.data, b"/Subtype /Image"));
+
+    Ok(())
+}
+
+#[test]
+fn test_render_normalized_svg() -> Result<()> {
+    // Glyphs referenced with <use> and sizes in ex, as MathJax writes them
+    let svg_data = r##"<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink" width="2ex" height="1ex" viewBox="0 0 20 10">
+        <defs><path id="glyph" d="M0 0 L10 0 L10 10 Z" /></defs>
+        <use xlink:href="#glyph" fill="currentColor" />
+        <use xlink:href="#glyph" x="10" fill="currentColor" />
+    </svg>"##;
+    let mut manager = SvgManager::new();
+    let mut render = |width| {
+        manager.process_render_request(request(
+            svg_data,
+            width,
+            None,
+            RenderOptions {
+                format: Some(ImageFormat::NormalizedSvg),
+                foreground: Some("#dcdccc".to_string()),
+                ..RenderOptions::default()
+            },
+        ))
+    };
+
+    let natural = render(None)?.bitmap;
+    let svg = String::from_utf8(natural.data)?;
+    // usvg takes an ex as half of the default 12px font size
+    assert!(svg.starts_with(r#"<svg width="12" height="6""#), "{svg}");
+    assert!(!svg.contains("<use") && !svg.contains('\n'));
+    assert!(svg.contains("#dcdccc"));
+
+    let wide = String::from_utf8(render(Some(32))?.bitmap.data)?;
+    assert!(wide.starts_with(r#"<svg width="32" height="16" viewBox="0 0 12 6""#));
+
+    // A scale does not change the document, nor the size it reports
+    let scaled = manager
+        .process_render_request(request(
+            svg_data,
+            None,
+            None,
+            RenderOptions {
+                format: Some(ImageFormat::NormalizedSvg),
+                scale: Some(2.0),
+                ..RenderOptions::default()
+            },
+        ))?
+        .bitmap;
+    assert!(String::from_utf8(scaled.data)?.starts_with(r#"<svg width="12" height="6""#));
+    assert_eq!((scaled.width, scaled.height), (12, 6));
+    assert_eq!(scaled.metrics.unwrap().height, 6.0);
+
+    Ok(())
+}
+
+#[test]
+fn test_render_size_variants() -> Result<()> {
+    let svg_data = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="20">
+        <rect width="10" height="20" fill="red" />
+    </svg>"#;
+    let request = |width| RenderRequest {
+        id: Some("tall".to_string()),
+        ..request(svg_data, width, None, RenderOptions::default())
+    };
+
+    let mut manager = SvgManager::new();
+    let small = manager.process_render_request(request(Some(10)))?;
+    let large = manager.process_render_request(request(Some(30)))?;
+    assert_eq!(small.id, "tall@10x_");
+    assert_eq!(large.id, "tall@30x_");
+    assert_eq!((large.bitmap.width, large.bitmap.height), (30, 60));
+
+    // Both sizes stay cached
+    let again = manager.process_render_request(request(Some(10)))?;
+    assert!(again.cached);
+    assert_eq!(again.bitmap.height, 20);
+
+    // The SVG id finds the most recently rendered size
+    assert_eq!(manager.get_bitmap("tall").unwrap().width, 30);
+
+    Ok(())
+}
+
+#[test]
+fn test_render_scale() -> Result<()> {
+    let svg_data = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="20">
+        <rect width="10" height="20" fill="red" />
+    </svg>"#;
+    let request = |width, scale| {
+        request(
+            svg_data,
+            width,
+            None,
+            RenderOptions {
+                scale,
+                ..RenderOptions::default()
+            },
+        )
+    };
+
+    let mut manager = SvgManager::new();
+    let plain = manager.process_render_request(request(None, None))?;
+    let hidpi = manager.process_render_request(request(None, Some(2.0)))?;
+    assert_ne!(plain.id, hidpi.id);
+    assert_eq!((plain.bitmap.width, plain.bitmap.height), (10, 20));
+    assert_eq!((hidpi.bitmap.width, hidpi.bitmap.height), (20, 40));
+    assert_eq!(
+        (hidpi.bitmap.logical_width, hidpi.bitmap.logical_height),
+        (10, 20)
+    );
+
+    // Requested sizes are logical too
+    let sized = manager.process_render_request(request(Some(30), Some(1.5)))?;
+    assert_eq!((sized.bitmap.width, sized.bitmap.height), (45, 90));
+    assert_eq!(sized.bitmap.logical_width, 30);
+
+    assert!(manager
+        .process_render_request(request(None, Some(0.0)))
+        .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_render_frame() -> Result<()> {
+    let svg_data = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="20" style="vertical-align: -1ex">
+        <rect width="10" height="20" fill="red" />
+    </svg>"#;
+    let request = |options| request(svg_data, None, None, options);
+
+    let mut manager = SvgManager::new();
+    let plain = manager.process_render_request(request(RenderOptions::default()))?;
+    let padded = manager.process_render_request(request(RenderOptions {
+        background: Some("blue".to_string()),
+        padding: Some("0.5em".to_string()),
+        format: Some(ImageFormat::Rgba),
+        ..RenderOptions::default()
+    }))?;
+    assert_ne!(plain.id, padded.id);
+    let bitmap = &padded.bitmap;
+    // An em is 12 pixels, so 6 on each side
+    assert_eq!((bitmap.width, bitmap.height), (22, 32));
+    assert_eq!(pixel(bitmap, 0, 0), [0, 0, 255, 255]);
+    assert_eq!(pixel(bitmap, 11, 16), [255, 0, 0, 255]);
+    let metrics = bitmap.metrics.unwrap();
+    assert_eq!((metrics.height, metrics.depth), (32.0, 12.0));
+
+    // Rounded corners leave the corner transparent, the border is drawn over the padding
+    let framed = manager.process_render_request(request(RenderOptions {
+        background: Some("blue".to_string()),
+        padding: Some("4px".to_string()),
+        border: Some("lime".to_string()),
+        border_width: Some(2.0),
+        border_radius: Some("6".to_string()),
+        scale: Some(2.0),
+        format: Some(ImageFormat::Rgba),
+        ..RenderOptions::default()
+    }))?;
+    let bitmap = &framed.bitmap;
+    assert_eq!((bitmap.width, bitmap.height), (36, 56));
+    assert_eq!(pixel(bitmap, 0, 0)[3], 0);
+    assert_eq!(pixel(bitmap, 18, 1), [0, 255, 0, 255]);
+    assert_eq!(pixel(bitmap, 18, 6), [0, 0, 255, 255]);
+
+    // The margin stays transparent around the background
+    let margined = manager.process_render_request(request(RenderOptions {
+        background: Some("blue".to_string()),
+        margin: Some("3px".to_string()),
+        padding: Some("1px".to_string()),
+        format: Some(ImageFormat::Rgba),
+        ..RenderOptions::default()
+    }))?;
+    let bitmap = &margined.bitmap;
+    assert_eq!((bitmap.width, bitmap.height), (18, 28));
+    assert_eq!(pixel(bitmap, 1, 1)[3], 0);
+    assert_eq!(pixel(bitmap, 3, 3), [0, 0, 255, 255]);
+    assert_eq!(pixel(bitmap, 4, 4), [255, 0, 0, 255]);
+    let metrics = bitmap.metrics.unwrap();
+    assert_eq!((metrics.height, metrics.depth), (28.0, 10.0));
+
+    // A border wider than the image still covers it, over the background
+    let bordered = manager.process_render_request(request(RenderOptions {
+        background: Some("blue".to_string()),
+        border: Some("lime".to_string()),
+        border_width: Some(30.0),
+        format: Some(ImageFormat::Rgba),
+        ..RenderOptions::default()
+    }))?;
+    let bitmap = &bordered.bitmap;
+    assert_eq!((bitmap.width, bitmap.height), (10, 20));
+    assert_eq!(pixel(bitmap, 0, 0), [0, 255, 0, 255]);
+    assert_eq!(pixel(bitmap, 5, 10), [0, 255, 0, 255]);
+
+    assert!(manager
+        .process_render_request(request(RenderOptions {
+            padding: Some("wide".to_string()),
+            ..RenderOptions::default()
+        }))
+        .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_render_fit() -> Result<()> {
+    let svg_data = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="20">
+        <rect width="10" height="20" fill="red" />
+    </svg>"#;
+    let request = |width, height, fit| {
+        request(
+            svg_data,
+            width,
+            height,
+            RenderOptions {
+                background: Some("blue".to_string()),
+                fit,
+                format: Some(ImageFormat::Rgba),
+                ..RenderOptions::default()
+            },
+        )
+    };
+    let (red, blue) = (vec![255, 0, 0, 255], vec![0, 0, 255, 255]);
+
+    let mut manager = SvgManager::new();
+    let stretch = manager.process_render_request(request(Some(40), Some(40), None))?;
+    assert_eq!(pixel(&stretch.bitmap, 0, 20), red);
+
+    // Letterboxed with the background
+    let contain =
+        manager.process_render_request(request(Some(40), Some(40), Some(Fit::Contain)))?;
+    assert_ne!(contain.id, stretch.id);
+    let bitmap = &contain.bitmap;
+    assert_eq!((bitmap.width, bitmap.height), (40, 40));
+    assert_eq!(pixel(bitmap, 5, 20), blue);
+    assert_eq!(pixel(bitmap, 20, 20), red);
+    assert_eq!(pixel(bitmap, 35, 20), blue);
+
+    let cover = manager.process_render_request(request(Some(40), Some(40), Some(Fit::Cover)))?;
+    assert_eq!((cover.bitmap.width, cover.bitmap.height), (40, 40));
+    assert_eq!(pixel(&cover.bitmap, 0, 0), red);
+
+    // Bounds shrink keeping the aspect ratio, but never enlarge
+    let max = |width, height| {
+        SvgManager::new()
+            .process_render_request(request(width, height, Some(Fit::Max)))
+            .map(|r| (r.bitmap.width, r.bitmap.height))
+    };
+    assert_eq!(max(Some(5), None)?, (5, 10));
+    assert_eq!(max(Some(8), Some(10))?, (5, 10));
+    assert_eq!(max(Some(600), None)?, (10, 20));
+
+    Ok(())
+}
+
+#[test]
+fn test_render_trim() -> Result<()> {
+    // A small shape in a large, mostly empty view box
+    let svg_data = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="100">
+        <rect x="20" y="30" width="10" height="20" fill="red" />
+    </svg>"#;
+    let mut manager = SvgManager::new();
+    let mut render = |trim, width| {
+        manager.process_render_request(request(
+            svg_data,
+            width,
+            None,
+            RenderOptions {
+                trim,
+                format: Some(ImageFormat::Rgba),
+                ..RenderOptions::default()
+            },
+        ))
+    };
+
+    let plain = render(None, None)?.bitmap;
+    assert_eq!((plain.width, plain.height), (100, 100));
+    assert_eq!(plain.trim_offset, None);
+
+    for trim in [Trim::Bounds, Trim::Transparent] {
+        let trimmed = render(Some(trim), None)?.bitmap;
+        assert_eq!((trimmed.width, trimmed.height), (10, 20));
+        assert_eq!(trimmed.trim_offset, Some(TrimOffset { x: 20.0, y: 30.0 }));
+        assert!(trimmed.data.chunks_exact(4).all(|p| p == [255, 0, 0, 255]));
+    }
+
+    // The requested size applies to the trimmed area
+    let sized = render(Some(Trim::Bounds), Some(30))?.bitmap;
+    assert_eq!((sized.width, sized.height), (30, 60));
+    assert_eq!(&sized.data[..4], &[255, 0, 0, 255]);
+
+    // Drawn pixels are looked for at the requested size, not the natural size of a huge view box
+    let huge = r#"<svg xmlns="http://www.w3.org/2000/svg" width="60000" height="60000">
+        <rect x="30000" y="30000" width="6000" height="6000" fill="red" />
+    </svg>"#;
+    let trimmed = SvgManager::new()
+        .process_render_request(request(
+            huge,
+            Some(100),
+            None,
+            RenderOptions {
+                trim: Some(Trim::Transparent),
+                format: Some(ImageFormat::Rgba),
+                ..RenderOptions::default()
+            },
+        ))?
+        .bitmap;
+    assert_eq!((trimmed.width, trimmed.height), (100, 100));
+    assert_eq!(
+        trimmed.trim_offset,
+        Some(TrimOffset {
+            x: 30000.0,
+            y: 30000.0
+        })
+    );
+    assert!(trimmed.data.chunks_exact(4).all(|p| p == [255, 0, 0, 255]));
+
+    // Areas smaller than a pixel still get one, also when scaled to a requested width
+    let speck = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10">
+        <rect x="2" y="2" width="0.3" height="0.3" fill="red" />
+    </svg>"#;
+    let rule = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="10">
+        <rect y="5" width="100" height="0.3" fill="red" />
+    </svg>"#;
+    for (svg_data, width, size) in [(speck, None, (1, 1)), (rule, Some(50), (50, 1))] {
+        let bitmap = SvgManager::new()
+            .process_render_request(request(
+                svg_data,
+                width,
+                None,
+                RenderOptions {
+                    trim: Some(Trim::Bounds),
+                    ..RenderOptions::default()
+                },
+            ))?
+            .bitmap;
+        assert_eq!((bitmap.width, bitmap.height), size);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_cache_eviction() -> Result<()> {
+    let svg_data = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10"/>"#;
+    let mut manager = SvgManager::with_limits(CacheLimits {
+        svgs: CacheLimit {
+            max_bytes: Some(2 * svg_data.len()),
+            max_entries: None,
+        },
+        bitmaps: CacheLimit {
+            max_bytes: None,
+            max_entries: Some(2),
+        },
+    });
+
+    let id = manager.store_svg(svg_data, None);
+    manager.render_svg(&id, Some(10), None)?;
+    manager.render_svg(&id, Some(20), None)?;
+    // Using the first size makes the second the least recently used
+    assert!(manager.get_bitmap(&format!("{id}@10x_")).is_some());
+    manager.render_svg(&id, Some(30), None)?;
+
+    assert!(manager.get_bitmap(&format!("{id}@10x_")).is_some());
+    assert!(manager.get_bitmap(&format!("{id}@20x_")).is_none());
+    assert!(manager.get_bitmap(&format!("{id}@30x_")).is_some());
+
+    // Only two SVG sources fit in the byte budget
+    manager.store_svg(svg_data, Some("b".to_string()));
+    manager.store_svg(svg_data, Some("c".to_string()));
+    assert!(manager.get_svg(&id).is_none());
+    assert!(manager.get_bitmap(&id).is_none());
+
+    let stats = manager.stats();
+    assert_eq!(stats.svgs.entries, 2);
+    assert_eq!(stats.svgs.bytes, 2 * svg_data.len());
+    assert_eq!(stats.svgs.evictions, 1);
+    assert_eq!(stats.bitmaps.entries, 2);
+    assert_eq!(stats.bitmaps.evictions, 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_shared_render() -> Result<()> {
+    let manager = SharedSvgManager::new();
+    let svg_data = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10"/>"#;
+    let request = |size| request(svg_data, Some(size), None, RenderOptions::default());
+
+    // Renders of different sizes run side by side, identical ones are shared
+    let tasks: Vec<_> = [10, 20, 10, 20]
+        .into_iter()
+        .map(|size| {
+            let manager = manager.clone();
+            tokio::spawn(async move { manager.render(request(size)).await })
+        })
+        .collect();
+    for (task, size) in tasks.into_iter().zip([10, 20, 10, 20]) {
+        assert_eq!(task.await??.bitmap.width, size);
+    }
+    assert_eq!(manager.stats().bitmaps.entries, 2);
+    assert_eq!(manager.stats().svgs.entries, 1);
+
+    assert!(manager.render(request(10)).await?.cached);
+
+    // The blocking path fills the same cache
+    assert_eq!(
+        manager.process_render_request(request(30))?.bitmap.width,
+        30
+    );
+    assert_eq!(manager.stats().bitmaps.entries, 3);
+    assert!(manager.render(request(30)).await?.cached);
+
+    Ok(())
+}
@@ -0,0 +1,34 @@
+use svgear::SvgMetrics;
+
+#[test]
+fn test_mathjax_metrics() {
+    let svg = r#"<svg xmlns:xlink="http://www.w3.org/1999/xlink" width="2ex" height="3ex" style="vertical-align: -1ex;" viewBox="0 -755.9 865 1008.6" role="img" focusable="false" xmlns="http://www.w3.org/2000/svg"><path stroke-width="1" d=""/></svg>"#;
+
+    let metrics = SvgMetrics::from_svg(svg).unwrap();
+    assert_eq!(metrics.width, 12.0);
+    assert_eq!(metrics.height, 18.0);
+    assert_eq!(metrics.depth, 6.0);
+    assert_eq!(metrics.ascent, 12.0);
+    assert_eq!(metrics.ascent_percent(), 67);
+
+    let scaled = metrics.scaled_to(24.0, 36.0);
+    assert_eq!(scaled.width, 24.0);
+    assert_eq!(scaled.depth, 12.0);
+
+    // Stretched images scale each axis on its own
+    let stretched = metrics.scaled_to(48.0, 9.0);
+    assert_eq!(stretched.width, 48.0);
+    assert_eq!((stretched.ascent, stretched.depth), (6.0, 3.0));
+}
+
+#[test]
+fn test_plain_svg_metrics() {
+    let svg = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="50"></svg>"#;
+
+    let metrics = SvgMetrics::from_svg(svg).unwrap();
+    assert_eq!(metrics.height, 50.0);
+    assert_eq!(metrics.depth, 0.0);
+    assert_eq!(metrics.ascent_percent(), 100);
+
+    assert!(SvgMetrics::from_svg("<svg viewBox=\"0 0 1 1\"></svg>").is_none());
+}
@@ -0,0 +1,195 @@
+use anyhow::Result;
+use std::future::Future;
+use std::pin::pin;
+use std::task::Poll;
+use std::time::Duration;
+use svgear::error::SvgearError;
+use svgear::painter::{NodeServer, NodeServerPool, PoolStats};
+use svgear::{PaintParams, PaintType};
+
+const FAKE_SERVER: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests/fixtures/fake_server.mjs"
+);
+
+fn fake_server_path() -> String {
+    FAKE_SERVER.to_string()
+}
+
+fn fake_server() -> NodeServer {
+    NodeServer::new(fake_server_path())
+}
+
+fn params(content: &str) -> PaintParams {
+    PaintParams {
+        ty: PaintType::InlineTeX,
+        content: content.to_string(),
+        timeout_ms: None,
+    }
+}
+
+#[tokio::test]
+async fn test_concurrent_requests_match_responses() -> Result<()> {
+    let server = fake_server();
+
+    // The slow request is answered last, but each caller still gets its own SVG
+    let (slow, fast) = tokio::join!(server.paint(params("slow:a")), server.paint(params("b")));
+    assert_eq!(slow?, "<svg><text>a</text></svg>");
+    assert_eq!(fast?, "<svg><text>b</text></svg>");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_error_response() -> Result<()> {
+    let server = fake_server();
+
+    let err = server
+        .paint(params("fail:Undefined control sequence \\foo"))
+        .await
+        .unwrap_err();
+    assert_eq!(err.to_string(), "Undefined control sequence \\foo at col 6");
+    let Some(SvgearError::Paint(error)) = err.downcast_ref::<SvgearError>() else {
+        panic!("expected a paint error, got {err:?}");
+    };
+    assert_eq!(error.kind.as_deref(), Some("tex"));
+    assert_eq!(error.column, Some(6));
+
+    // The process keeps serving after an error
+    assert_eq!(
+        server.paint(params("c")).await?,
+        "<svg><text>c</text></svg>"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_pool_spreads_requests() -> Result<()> {
+    let pool = NodeServerPool::new(fake_server_path(), 2);
+    assert_eq!(
+        pool.stats(),
+        PoolStats {
+            workers: 2,
+            busy: 0,
+            idle: 2,
+            queued: 0,
+        }
+    );
+
+    let mut a = pin!(pool.paint(params("slow:a")));
+    let mut b = pin!(pool.paint(params("slow:b")));
+    let mut c = pin!(pool.paint(params("slow:c")));
+
+    // Two requests occupy both workers on their first poll, the third waits for one of them
+    std::future::poll_fn(|cx| {
+        assert!(a.as_mut().poll(cx).is_pending());
+        assert!(b.as_mut().poll(cx).is_pending());
+        assert!(c.as_mut().poll(cx).is_pending());
+        Poll::Ready(())
+    })
+    .await;
+    let stats = pool.stats();
+    assert_eq!((stats.busy, stats.idle, stats.queued), (2, 0, 1));
+
+    let (a, b, c) = tokio::join!(a, b, c);
+    for (svg, expected) in [a?, b?, c?].into_iter().zip(["a", "b", "c"]) {
+        assert_eq!(svg, format!("<svg><text>{expected}</text></svg>"));
+    }
+    assert_eq!(pool.stats().idle, 2);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_restart_after_crash() -> Result<()> {
+    let server = fake_server();
+    assert_eq!(
+        server.paint(params("a")).await?,
+        "<svg><text>a</text></svg>"
+    );
+
+    // The request is retried once on a fresh process, which crashes again
+    let err = server.paint(params("crash")).await.unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<SvgearError>(),
+        Some(SvgearError::NodeExited)
+    ));
+    assert_eq!(server.restarts(), 1);
+
+    // The next request gets another fresh process
+    assert_eq!(
+        server.paint(params("b")).await?,
+        "<svg><text>b</text></svg>"
+    );
+    assert_eq!(server.restarts(), 2);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_timeout_recycles_process() -> Result<()> {
+    let server = fake_server();
+    let pid = server.paint(params("pid")).await?;
+
+    let err = server
+        .paint(PaintParams {
+            timeout_ms: Some(100),
+            ..params("hang")
+        })
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<SvgearError>(),
+        Some(SvgearError::Timeout(_))
+    ));
+    assert_eq!(SvgearError::kind_of(&err), Some("timeout"));
+
+    // The hung process is replaced without counting as a crash
+    assert_eq!(server.recycles(), 1);
+    assert_ne!(server.paint(params("pid")).await?, pid);
+    assert_eq!(server.restarts(), 0);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_dropped_paint_recycles_process() -> Result<()> {
+    let server = fake_server();
+    let pid = server.paint(params("pid")).await?;
+
+    // The caller gives up on the request without a timeout of its own
+    tokio::select! {
+        _ = server.paint(params("hang")) => panic!("the hung request finished"),
+        _ = tokio::time::sleep(Duration::from_millis(100)) => {}
+    }
+
+    assert_eq!(server.recycles(), 1);
+    assert_ne!(server.paint(params("pid")).await?, pid);
+    assert_eq!(server.restarts(), 0);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_timeout_includes_queue() -> Result<()> {
+    let pool = NodeServerPool::new(fake_server_path(), 1);
+
+    // The only worker hangs, so the second request times out before reaching it
+    let hung = pool.paint(params("hang"));
+    let queued = pool.paint(PaintParams {
+        timeout_ms: Some(100),
+        ..params("a")
+    });
+    tokio::select! {
+        // The hung request is polled first, so it takes the worker
+        biased;
+        _ = hung => panic!("the hung request finished"),
+        result = queued => {
+            let err = result.unwrap_err();
+            assert_eq!(SvgearError::kind_of(&err), Some("timeout"));
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,160 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use svgear::error::SvgearError;
+use svgear::{PaintBackend, PaintParams, PaintType, Painter};
+
+mod common;
+
+use common::CountingBackend;
+
+#[derive(Debug)]
+struct EchoBackend;
+
+#[async_trait]
+impl PaintBackend for EchoBackend {
+    async fn paint(&self, params: PaintParams) -> Result<String> {
+        Ok(format!("<svg>{}</svg>", params.content))
+    }
+}
+
+#[tokio::test]
+async fn test_custom_backend() -> Result<()> {
+    let mut painter = Painter::new();
+    let ty = PaintType::Custom("echo".to_string());
+    painter.register_backend(ty.clone(), Arc::new(EchoBackend));
+
+    let svg = painter
+        .paint(PaintParams {
+            ty,
+            content: "hello".to_string(),
+            timeout_ms: None,
+        })
+        .await?;
+    assert_eq!(svg, "<svg>hello</svg>");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_paint_memo() -> Result<()> {
+    let backend = Arc::new(CountingBackend::default());
+    let ty = PaintType::Custom("count".to_string());
+    let mut painter = Painter::new();
+    painter.register_backend(ty.clone(), backend.clone());
+
+    let params = |content: &str, timeout_ms| PaintParams {
+        ty: ty.clone(),
+        content: content.to_string(),
+        timeout_ms,
+    };
+    painter.paint(params("a", None)).await?;
+    // The timeout does not change the SVG, so the memo still applies
+    assert_eq!(painter.paint(params("a", Some(100))).await?, "<svg>a</svg>");
+    assert_eq!(backend.count(), 1);
+
+    // Clones share the memo
+    painter.clone().paint(params("b", None)).await?;
+    painter.paint(params("b", None)).await?;
+    assert_eq!(backend.count(), 2);
+    assert_eq!(painter.memo_stats().entries, 2);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_concurrent_paints_coalesce() -> Result<()> {
+    let backend = Arc::new(CountingBackend::default());
+    let ty = PaintType::Custom("count".to_string());
+    let mut painter = Painter::new();
+    painter.register_backend(ty.clone(), backend.clone());
+
+    let paint = |content: &str| {
+        painter.paint(PaintParams {
+            ty: ty.clone(),
+            content: content.to_string(),
+            timeout_ms: None,
+        })
+    };
+    let (a, b, c) = tokio::join!(paint("a"), paint("a"), paint("a"));
+    assert_eq!((a?, b?, c?).2, "<svg>a</svg>");
+    assert_eq!(backend.count(), 1);
+
+    // Every caller sees the error, with its kind and position
+    let (first, second) = tokio::join!(paint("fail"), paint("fail"));
+    assert_eq!(backend.count(), 2);
+    for err in [first.unwrap_err(), second.unwrap_err()] {
+        assert_eq!(SvgearError::kind_of(&err), Some("paint"));
+        assert_eq!(err.to_string(), "Failed at line 2 col 3");
+        let Some(SvgearError::Paint(error)) = SvgearError::find(&err) else {
+            panic!("expected a paint error, got {err:?}");
+        };
+        assert_eq!((error.line, error.column), (Some(2), Some(3)));
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_concurrent_paints_keep_their_timeouts() -> Result<()> {
+    let backend = Arc::new(CountingBackend::default());
+    let ty = PaintType::Custom("count".to_string());
+    let mut painter = Painter::new();
+    painter.register_backend(ty.clone(), backend.clone());
+
+    let paint = |content: &str, timeout_ms| {
+        painter.paint(PaintParams {
+            ty: ty.clone(),
+            content: content.to_string(),
+            timeout_ms,
+        })
+    };
+    let is_timeout = |result: Result<String>| {
+        result.is_err_and(|err| SvgearError::kind_of(&err) == Some("timeout"))
+    };
+
+    // A caller waiting on a slower paint gives up at its own deadline
+    let (slow, impatient) = tokio::join!(paint("a", None), paint("a", Some(5)));
+    assert_eq!(slow?, "<svg>a</svg>");
+    assert!(is_timeout(impatient));
+    assert_eq!(backend.count(), 1);
+
+    // When the first caller gives up, the waiting one paints without its deadline
+    let (impatient, patient) = tokio::join!(paint("b", Some(5)), paint("b", None));
+    assert!(is_timeout(impatient));
+    assert_eq!(patient?, "<svg>b</svg>");
+    assert_eq!(backend.count(), 3);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_missing_backend() {
+    let painter = Painter::new();
+    let err = painter
+        .paint(PaintParams {
+            ty: PaintType::Mermaid,
+            content: "graph TD; A-->B".to_string(),
+            timeout_ms: None,
+        })
+        .await
+        .unwrap_err();
+
+    assert!(matches!(
+        err.downcast_ref::<SvgearError>(),
+        Some(SvgearError::NoBackend(PaintType::Mermaid))
+    ));
+}
+
+#[test]
+fn test_paint_type_names() -> Result<()> {
+    for ty in PaintType::BUILTIN {
+        assert_eq!(ty.to_string().parse::<PaintType>()?, ty);
+    }
+    assert!("inlinemathml".parse::<PaintType>()?.is_inline());
+    assert!(!"asciimath".parse::<PaintType>()?.is_inline());
+    assert!("png".parse::<PaintType>().is_err());
+
+    Ok(())
+}